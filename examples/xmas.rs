@@ -1,36 +1,23 @@
-use std::{thread, time};
-use tplinker::{
-    capabilities::Light, datatypes::SetLightState, devices::Device, discovery::discover,
-};
+use std::{thread, time::Duration};
+use tplinker::{devices::ColourGroup, discovery::discover};
 
 fn main() {
-    let devices = discover()
-        .unwrap()
-        .iter()
-        .filter_map(|(addr, data)| match Device::from_data(*addr, &data) {
-            Device::LB110(device) => Some(device),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
+    let bulbs = ColourGroup::from_discovered(&discover().unwrap());
+    if bulbs.is_empty() {
+        eprintln!("No colour bulbs found");
+        return;
+    }
 
-    let mut index = 0;
+    let step = Duration::from_secs(1);
+    let mut hue = 0;
     loop {
-        let device = &devices[index];
-        let second = time::Duration::from_secs(1);
-
-        let _ = device.set_light_state(SetLightState {
-            on_off: Some(1),
-            brightness: Some(100),
-            ..Default::default()
-        });
-        thread::sleep(second);
-        let _ = device.set_light_state(SetLightState {
-            on_off: Some(0),
-            brightness: Some(0),
-            ..Default::default()
-        });
+        for (addr, result) in bulbs.set_hsv_with_transition(hue, 100, 100, step) {
+            if let Err(err) = result {
+                eprintln!("{}: {}", addr, err);
+            }
+        }
+        thread::sleep(step);
 
-        index = index + 1;
-        index = index % devices.len()
+        hue = (hue + 30) % 360;
     }
 }
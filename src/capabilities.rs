@@ -3,16 +3,28 @@
 //! Different devices have different combinations of capabilities available to them. To
 //! make these easier to work with in a type safe and consistent way sets of functions
 //! are grouped together into capability traits that can be implemented on devices.
-use std::time::Duration;
+use std::{
+    convert::TryFrom,
+    thread,
+    time::{Duration, SystemTime},
+};
 
 use serde::de::DeserializeOwned;
 use serde_json::json;
 
 use crate::{
+    colour::{hsv_to_rgb, rgb_to_hsv},
     datatypes::{
-        DeviceData, GetLightStateResult, LightState, SetLightState, SysInfo, LIGHT_SERVICE,
+        check_brightness, check_color_temp, check_hsv, check_hue, check_saturation, clamp_hsv,
+        transition_period, AddRuleResult, AntiTheftRules, Behavior, CloudInfo, DeviceData,
+        DeviceIcon, DeviceTime, DimmerParameters, EmeterGain, EmeterRealtime, EnergyRealtime,
+        FirmwareInfo, FirmwareList, GetEmeterRealtimeResult, GetLightStateResult, LightDetails,
+        LightMode, LightState, LightStateUpdate, PlugEmeterRealtime, PowerOnBehaviorResult,
+        ProtectionThresholds, SetLightState, SmartlifeEmeterRealtime, SysInfo, SysInfoChild,
+        Timezone, COMMON_EMETER, EMETER, LB120_COLOR_TEMP_RANGE, LIGHT_SERVICE,
     },
-    error::{Error, Result},
+    discovery,
+    error::{Error, Result, SectionError},
 };
 
 /// The basic set of functions available to all TPLink smart devices
@@ -27,6 +39,27 @@ pub trait DeviceActions {
     /// a problem decoding the response.
     fn send<T: DeserializeOwned>(&self, msg: &str) -> Result<T>;
 
+    /// Send a message to a device, waiting at most `timeout` for its response
+    ///
+    /// This overrides the transport's timeout for a single command, for example
+    /// a short timeout to quickly check many devices. Devices that cannot change
+    /// the timeout use the transport's own, which is what the default does.
+    fn send_with_timeout<T: DeserializeOwned>(&self, msg: &str, _timeout: Duration) -> Result<T> {
+        self.send(msg)
+    }
+
+    /// Send a message to a device and return its response as JSON
+    ///
+    /// This is useful for commands that are not otherwise supported.
+    fn send_value(&self, msg: &str) -> Result<serde_json::Value> {
+        self.send(msg)
+    }
+
+    /// Send a JSON message to a device and return its response as JSON
+    fn send_json(&self, value: &serde_json::Value) -> Result<serde_json::Value> {
+        self.send_value(&value.to_string())
+    }
+
     /// Get system information
     fn sysinfo(&self) -> Result<SysInfo> {
         Ok(self
@@ -34,6 +67,41 @@ pub trait DeviceActions {
             .into_sysinfo())
     }
 
+    /// Get system information, waiting at most `timeout` for it
+    ///
+    /// See [`send_with_timeout`](#method.send_with_timeout).
+    fn sysinfo_with_timeout(&self, timeout: Duration) -> Result<SysInfo> {
+        Ok(self
+            .send_with_timeout::<DeviceData>(r#"{"system":{"get_sysinfo":null}}"#, timeout)?
+            .into_sysinfo())
+    }
+
+    /// Get system information as JSON, without parsing it into a [`SysInfo`](../datatypes/struct.SysInfo.html)
+    ///
+    /// New models and firmware sometimes add or change fields so that
+    /// [`sysinfo`](#method.sysinfo) fails. This returns the system information
+    /// as the device sent it so those fields can be inspected and reported.
+    fn raw_sysinfo_json(&self) -> Result<serde_json::Value> {
+        let value = self.send_value(r#"{"system":{"get_sysinfo":null}}"#)?;
+        [
+            "/system/get_sysinfo",
+            "/smartlife.iot.common.system/get_sysinfo",
+        ]
+        .iter()
+        .find_map(|pointer| value.pointer(pointer))
+        .cloned()
+        .ok_or_else(|| Error::from(format!("Invalid response format: {}", value)))
+    }
+
+    /// Get system information along with energy usage and light state
+    ///
+    /// This sends the same combined query as
+    /// [`discover`](../discovery/fn.discover.html) so a full status can be read
+    /// in one request. Sections the device does not support hold their error.
+    fn device_data(&self) -> Result<DeviceData> {
+        self.send(discovery::QUERY)
+    }
+
     /// Get the alias of the device
     ///
     /// This is a user defined name for the device.
@@ -43,8 +111,10 @@ pub trait DeviceActions {
 
     /// Set the alias of the device
     ///
-    /// This is a user defined name for the device.
+    /// This is a user defined name for the device. It must be at most 31 bytes
+    /// when encoded as UTF-8 and must not contain control characters.
     fn set_alias(&self, alias: &str) -> Result<()> {
+        check_alias(alias)?;
         let command = json!({
             "system": {"set_dev_alias": {"alias": alias}}
         })
@@ -66,6 +136,25 @@ pub trait DeviceActions {
         }
     }
 
+    /// Get the icon set for the device in the Kasa app
+    fn get_icon(&self) -> Result<DeviceIcon> {
+        parse_section(
+            &self.send(r#"{"system":{"get_dev_icon":null}}"#)?,
+            "/system/get_dev_icon",
+        )
+    }
+
+    /// Set the icon for the device in the Kasa app
+    ///
+    /// The icon is a base64 encoded image identified by `hash`.
+    fn set_icon(&self, hash: &str, icon: &str) -> Result<()> {
+        let command = json!({
+            "system": {"set_dev_icon": {"hash": hash, "icon": icon}}
+        })
+        .to_string();
+        check_command_error(&self.send(&command)?, "/system/set_dev_icon/err_code")
+    }
+
     /// Reboot the device in 1 second
     fn reboot(&self) -> Result<()> {
         self.reboot_with_delay(Duration::from_secs(1))
@@ -80,6 +169,38 @@ pub trait DeviceActions {
 
         check_command_error(&self.send(&command)?, "/system/reboot/err_code")
     }
+
+    /// Reset the device to its factory settings in 1 second
+    ///
+    /// **Warning:** this is destructive. The device forgets its wifi network,
+    /// alias, schedules and cloud binding, and has to be set up again with the
+    /// Kasa app before it can be used.
+    fn factory_reset(&self) -> Result<()> {
+        check_command_error(
+            &self.send(r#"{"system":{"reset":{"delay":1}}}"#)?,
+            "/system/reset/err_code",
+        )
+    }
+
+    /// Change the MAC address of the device
+    ///
+    /// **Warning:** a device with a MAC address that clashes with another device,
+    /// or that the router does not expect, may not be able to join the network.
+    ///
+    /// This requires the `mac` feature.
+    #[cfg(any(test, feature = "mac"))]
+    fn set_mac_address(&self, mac: [u8; 6]) -> Result<()> {
+        let mac = mac
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join("-");
+        let command = json!({
+            "system": {"set_mac_addr": {"mac": mac}}
+        })
+        .to_string();
+        check_command_error(&self.send(&command)?, "/system/set_mac_addr/err_code")
+    }
 }
 
 /// Devices that can be switched on and off
@@ -87,12 +208,13 @@ pub trait DeviceActions {
 /// All devices support this trait.
 pub trait Switch: DeviceActions {
     /// Check whether the device is on
+    ///
+    /// Any non-zero relay state is treated as on, see
+    /// [`SysInfo::relay_is_on`](../datatypes/struct.SysInfo.html#method.relay_is_on).
     fn is_on(&self) -> Result<bool> {
         self.sysinfo()?
-            .relay_state
-            .map_or(Err(Error::from("No relay state")), |relay_state| {
-                Ok(relay_state > 0)
-            })
+            .relay_is_on()
+            .ok_or_else(|| Error::from("No relay state"))
     }
 
     /// Check whether the device is off
@@ -103,7 +225,7 @@ pub trait Switch: DeviceActions {
     /// Switch the device on
     fn switch_on(&self) -> Result<()> {
         check_command_error(
-            &self.send(&r#"{"system":{"set_relay_state":{"state":1}}}"#)?,
+            &self.send(r#"{"system":{"set_relay_state":{"state":1}}}"#)?,
             "/system/set_relay_state/err_code",
         )
     }
@@ -111,17 +233,42 @@ pub trait Switch: DeviceActions {
     /// Switch the device off
     fn switch_off(&self) -> Result<()> {
         check_command_error(
-            &self.send(&r#"{"system":{"set_relay_state":{"state":0}}}"#)?,
+            &self.send(r#"{"system":{"set_relay_state":{"state":0}}}"#)?,
             "/system/set_relay_state/err_code",
         )
     }
 
+    /// Switch the device on and read the state back to check it worked
+    ///
+    /// Some devices report an error for a command that worked, or no error for
+    /// one that did not, so the state read back is what counts. Returns an error
+    /// if the device is still off.
+    fn switch_on_verified(&self) -> Result<()> {
+        verify_switch(self.switch_on(), self.is_on()?, true)
+    }
+
+    /// Switch the device off and read the state back to check it worked
+    ///
+    /// See [`switch_on_verified`](#method.switch_on_verified).
+    fn switch_off_verified(&self) -> Result<()> {
+        verify_switch(self.switch_off(), self.is_on()?, false)
+    }
+
     /// Toggle the device's on state
     ///
     /// If the device is on, switch it off.
     /// If the device is off, switch it on.
     fn toggle(&self) -> Result<bool> {
-        if self.is_on()? {
+        self.toggle_from(self.is_on()?)
+    }
+
+    /// Toggle the device's on state given whether it is currently on
+    ///
+    /// This saves reading the state when the caller already knows it. If the
+    /// state has changed since it was read the device is switched to the same
+    /// state again. Returns whether the device is now on.
+    fn toggle_from(&self, known_state: bool) -> Result<bool> {
+        if known_state {
             self.switch_off()?;
             Ok(false)
         } else {
@@ -136,16 +283,11 @@ pub trait Switch: DeviceActions {
 /// This is supported by power strips like the HS300
 pub trait MultiSwitch: DeviceActions {
     /// Check whether the specified outlet is on
+    ///
+    /// The index is the one in the outlet's child id, as used by
+    /// [`switch`](#method.switch), not its position in the system information.
     fn is_on(&self, index: usize) -> Result<bool> {
-        self.sysinfo()?
-            .children
-            .map_or(Err(Error::from("No relay state")), |children| {
-                children
-                    .get(index)
-                    .map_or(Err(Error::from("Invalid outlet index")), |child| {
-                        Ok(child.state > 0)
-                    })
-            })
+        Ok(find_outlet(&self.sysinfo()?, index)?.is_on())
     }
 
     /// Check whether the specified outlet is off
@@ -153,6 +295,13 @@ pub trait MultiSwitch: DeviceActions {
         Ok(!self.is_on(index)?)
     }
 
+    /// How long the specified outlet has been switched on for, zero if it is off
+    ///
+    /// The index is the same as for [`is_on`](#method.is_on).
+    fn uptime(&self, index: usize) -> Result<Duration> {
+        Ok(find_outlet(&self.sysinfo()?, index)?.uptime())
+    }
+
     /// Switch the specified outlet on
     fn switch_on(&self, index: usize) -> Result<()> {
         self.switch(index, true)
@@ -165,7 +314,7 @@ pub trait MultiSwitch: DeviceActions {
 
     /// Switch the specified outlet to a particular on/off value
     fn switch(&self, index: usize, on: bool) -> Result<()> {
-        let id = format!("{}{:0>2}", self.sysinfo()?.device_id, index);
+        let id = outlet_id(&self.sysinfo()?, index);
         let state = if on { 1 } else { 0 };
         check_command_error(
             &self.send(&json!({"context": {"child_ids": [id]}, "system": {"set_relay_state": {"state": state}}}).to_string())?,
@@ -178,7 +327,14 @@ pub trait MultiSwitch: DeviceActions {
     /// If the specified outlet is on, switch it off.
     /// If the specified outlet is off, switch it on.
     fn toggle(&self, index: usize) -> Result<bool> {
-        if self.is_on(index)? {
+        self.toggle_from(index, self.is_on(index)?)
+    }
+
+    /// Toggle the specified outlet's on state given whether it is currently on
+    ///
+    /// See [`Switch::toggle_from`](trait.Switch.html#method.toggle_from).
+    fn toggle_from(&self, index: usize, known_state: bool) -> Result<bool> {
+        if known_state {
             self.switch_off(index)?;
             Ok(false)
         } else {
@@ -192,6 +348,15 @@ pub trait MultiSwitch: DeviceActions {
 ///
 /// The LB class of devices support this trait.
 pub trait Light: DeviceActions {
+    /// Valid color temperature range of the bulb in degrees Kelvin, inclusive
+    ///
+    /// Defaults to the 2700K to 6500K of the LB120, models with a different range
+    /// override this. It is used to check every
+    /// [`update_light_state`](#method.update_light_state).
+    fn color_temp_range(&self) -> (u16, u16) {
+        LB120_COLOR_TEMP_RANGE
+    }
+
     /// Get the current state of the light
     fn get_light_state(&self) -> Result<LightState> {
         let command = json!({
@@ -204,6 +369,39 @@ pub trait Light: DeviceActions {
         data.light_state()
     }
 
+    /// Get whether the light is on along with its mode, colour and brightness
+    ///
+    /// This reads everything with a single request. When the light is off the
+    /// values are those it will have when switched on.
+    fn get_light_details(&self) -> Result<LightDetails> {
+        LightDetails::from_light_state(&self.get_light_state()?)
+            .ok_or_else(|| Error::from("No default on state in light state"))
+    }
+
+    /// Check whether the light is on
+    ///
+    /// Lights do not report a relay state so this reads the light state instead.
+    fn light_is_on(&self) -> Result<bool> {
+        Ok(self.get_light_state()?.on_off == 1)
+    }
+
+    /// Get the mode of the light
+    ///
+    /// In circadian mode the brightness and colour temperature follow the time
+    /// of day and changes made by hand do not stick.
+    fn get_mode(&self) -> Result<LightMode> {
+        Ok(self.get_light_details()?.mode)
+    }
+
+    /// Set the mode of the light
+    ///
+    /// Set [`LightMode::Normal`](../datatypes/enum.LightMode.html#variant.Normal)
+    /// to turn off circadian mode so that brightness and colour can be changed.
+    fn set_mode(&self, mode: LightMode) -> Result<()> {
+        self.update_light_state(LightStateUpdate::new().mode(mode))?;
+        Ok(())
+    }
+
     /// Set the state of the light
     ///
     /// This is a low level method, and has no validation. You should use one of the
@@ -217,98 +415,254 @@ pub trait Light: DeviceActions {
             },
         })
         .to_string();
-        self.send::<GetLightStateResult>(&command)?.light_state()
+        self.send::<GetLightStateResult>(&command)?
+            .light_state()?
+            .check_err_code()
+    }
+
+    /// Change the state of the light, leaving anything not in `update` unchanged
+    ///
+    /// Values are checked with [`LightStateUpdate::build_with_color_temp_range`](../datatypes/struct.LightStateUpdate.html#method.build_with_color_temp_range)
+    /// and the [`color_temp_range`](#method.color_temp_range) of the light before
+    /// anything is sent.
+    fn update_light_state(&self, update: LightStateUpdate) -> Result<LightState> {
+        self.set_light_state(update.build_with_color_temp_range(self.color_temp_range())?)
+    }
+
+    /// Switch the light on using one of its preferred states
+    ///
+    /// Preferred states are the presets stored on the device, for example from the
+    /// Kasa app. They are listed in the `preferred_states` field of the
+    /// [`SysInfo`](../datatypes/struct.SysInfo.html).
+    fn apply_preferred_state(&self, index: u8) -> Result<LightState> {
+        let preferred_state = self
+            .sysinfo()?
+            .preferred_states
+            .and_then(|states| states.into_iter().find(|state| state.index == index))
+            .ok_or_else(|| Error::from("Invalid preferred state index"))?;
+        self.update_light_state(
+            LightStateUpdate::new()
+                .on()
+                .hue(preferred_state.hue)
+                .saturation(preferred_state.saturation)
+                .brightness(preferred_state.brightness)
+                .color_temp(preferred_state.color_temp),
+        )
     }
 }
 
 /// Dimmable smart light devices
 pub trait Dimmer: Light {
     /// Get percentage brightness of bulb
+    ///
+    /// If the bulb is off this is the brightness it will have when switched on.
     fn brightness(&self) -> Result<u16> {
-        Ok(self.get_light_state()?.dft_on_state().brightness)
+        Ok(self.get_light_details()?.brightness)
     }
 
     /// Set percentage brightness of bulb
+    ///
+    /// This does not switch the bulb on. If it is off the brightness is used
+    /// when it is next switched on. See [`set_brightness_on`](#method.set_brightness_on).
     fn set_brightness(&self, brightness: u16) -> Result<()> {
-        if brightness > 100 {
-            Err(Error::from("Brightness must be between 0 and 100"))
-        } else {
-            self.set_light_state(SetLightState {
-                on_off: None,
-                hue: None,
-                saturation: None,
-                brightness: Some(brightness),
-                color_temp: None,
-            })?;
-            Ok(())
+        check_brightness(brightness)?;
+        self.update_light_state(LightStateUpdate::new().brightness(brightness))?;
+        Ok(())
+    }
+
+    /// Switch the bulb on at the given percentage brightness
+    fn set_brightness_on(&self, brightness: u16) -> Result<()> {
+        check_brightness(brightness)?;
+        self.update_light_state(LightStateUpdate::new().on().brightness(brightness))?;
+        Ok(())
+    }
+
+    /// Set percentage brightness of bulb, fading over the given duration
+    fn set_brightness_with_transition(&self, brightness: u16, transition: Duration) -> Result<()> {
+        check_brightness(brightness)?;
+        self.update_light_state(
+            LightStateUpdate::new()
+                .brightness(brightness)
+                .transition(transition),
+        )?;
+        Ok(())
+    }
+
+    /// Fade from the current brightness to `target` over the given duration in `steps` steps
+    ///
+    /// Each step uses the bulb's own transition so the ramp is smooth between steps.
+    /// This blocks until the last step has been sent, which then takes one more
+    /// step's worth of time to complete on the bulb.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use tplinker::{capabilities::Dimmer, devices::LB110};
+    ///
+    /// let bulb = LB110::new("192.168.0.99:9999").unwrap();
+    /// bulb.fade_to(100, Duration::from_secs(600), 20).unwrap();
+    /// ```
+    fn fade_to(&self, target: u16, over: Duration, steps: u32) -> Result<()> {
+        check_brightness(target)?;
+        if steps == 0 {
+            return Err(Error::from("Steps must be greater than 0"));
+        }
+        let current = i64::from(self.brightness()?);
+        let step_time = over / steps;
+        for step in 1..=steps {
+            if step > 1 {
+                thread::sleep(step_time);
+            }
+            let brightness =
+                current + (i64::from(target) - current) * i64::from(step) / i64::from(steps);
+            self.set_brightness_with_transition(brightness as u16, step_time)?;
         }
+        Ok(())
     }
 }
 
 /// Tunable color temperature smart light devices
+///
+/// Methods do not check that the bulb has a variable color temperature, which
+/// would take an extra request for every change. Bulbs with a fixed white are
+/// only rejected by
+/// [`Device::try_color_temperature`](../devices/enum.Device.html#method.try_color_temperature),
+/// which returns `None` for them.
 pub trait ColorTemperature: Light {
     /// Get color temperature of bulb by degrees of Kelvin
     fn color_temp(&self) -> Result<u16> {
-        Ok(self.get_light_state()?.dft_on_state().color_temp)
+        Ok(self.get_light_details()?.color_temp)
     }
 
     /// Set color temperature of bulb
     ///
-    /// Color temperature must be between 2700 and 6500.
+    /// Color temperature must be within [`color_temp_range`](trait.Light.html#method.color_temp_range).
+    /// Use [`Device::try_color_temperature`](../devices/enum.Device.html#method.try_color_temperature)
+    /// to check that a bulb has a variable color temperature.
     fn set_color_temp(&self, color_temp: u16) -> Result<()> {
-        if color_temp < 2700 || color_temp > 6500 {
-            Err(Error::from(
-                "Color temperature must be between 2700 and 6500",
-            ))
-        } else {
-            self.set_light_state(SetLightState {
-                on_off: None,
-                hue: None,
-                saturation: None,
-                brightness: None,
-                color_temp: Some(color_temp),
-            })?;
-            Ok(())
-        }
+        check_color_temp(color_temp, self.color_temp_range())?;
+        self.update_light_state(LightStateUpdate::new().color_temp(color_temp))?;
+        Ok(())
+    }
+
+    /// Set color temperature of bulb, fading over the given duration
+    ///
+    /// See [`set_color_temp`](#method.set_color_temp).
+    fn set_color_temp_with_transition(&self, color_temp: u16, transition: Duration) -> Result<()> {
+        check_color_temp(color_temp, self.color_temp_range())?;
+        self.update_light_state(
+            LightStateUpdate::new()
+                .color_temp(color_temp)
+                .transition(transition),
+        )?;
+        Ok(())
     }
 }
 
 /// Full colour smart light devices
+///
+/// Hue is in degrees between 0 and 360, where 360 is the same as 0 and is sent to
+/// the device as 0. Saturation and value (brightness) are percentages between 0
+/// and 100. Methods reject values outside these ranges, except for
+/// [`try_set_hsv`](#method.try_set_hsv) which brings them into range.
 pub trait Colour: Light {
     /// Get hue, saturation and value (brightness)
     fn get_hsv(&self) -> Result<(u16, u16, u16)> {
-        let light_state = self.get_light_state()?;
-        let dft_on_state = light_state.dft_on_state();
-
-        Ok((
-            dft_on_state.hue,
-            dft_on_state.saturation,
-            dft_on_state.brightness,
-        ))
+        let details = self.get_light_details()?;
+        Ok((details.hue, details.saturation, details.brightness))
     }
 
-    /// Get hue, saturation and value (brightness)
+    /// Set hue, saturation and value (brightness)
+    ///
+    /// All three are sent. To change one and leave the others as they are use
+    /// [`set_hue`](#method.set_hue), [`set_saturation`](#method.set_saturation)
+    /// or [`set_value`](#method.set_value).
     ///
     /// Hue must be between 0 and 360.
     /// Saturation must be between 0 and 100.
     /// Brightness must be between 0 and 100.
     fn set_hsv(&self, hue: u16, saturation: u16, brightness: u16) -> Result<()> {
-        if hue > 360 {
-            return Err(Error::from("Hue must be between 0 and 360"));
-        }
-        if saturation > 100 {
-            return Err(Error::from("Saturation must be between 0 and 100"));
-        }
-        if brightness > 100 {
-            return Err(Error::from("Brightness must be between 0 and 100"));
-        }
-        self.set_light_state(SetLightState {
-            on_off: None,
-            hue: Some(hue),
-            saturation: Some(saturation),
-            brightness: Some(brightness),
-            color_temp: None,
-        })?;
+        let (hue, saturation, brightness) = check_hsv(hue, saturation, brightness)?;
+        self.update_light_state(
+            LightStateUpdate::new()
+                .hue(hue)
+                .saturation(saturation)
+                .brightness(brightness),
+        )?;
+        Ok(())
+    }
+
+    /// Set hue, saturation and value (brightness), fading over the given duration
+    ///
+    /// Hue must be between 0 and 360.
+    /// Saturation must be between 0 and 100.
+    /// Brightness must be between 0 and 100.
+    fn set_hsv_with_transition(
+        &self,
+        hue: u16,
+        saturation: u16,
+        brightness: u16,
+        transition: Duration,
+    ) -> Result<()> {
+        let (hue, saturation, brightness) = check_hsv(hue, saturation, brightness)?;
+        self.update_light_state(
+            LightStateUpdate::new()
+                .hue(hue)
+                .saturation(saturation)
+                .brightness(brightness)
+                .transition(transition),
+        )?;
+        Ok(())
+    }
+
+    /// Set hue, saturation and value (brightness), bringing them into range
+    ///
+    /// Hue wraps around the colour wheel, so 400 becomes 40. Saturation and
+    /// brightness above 100 are capped at 100. Returns the values that were sent.
+    fn try_set_hsv(&self, hue: u16, saturation: u16, brightness: u16) -> Result<(u16, u16, u16)> {
+        let (hue, saturation, brightness) = clamp_hsv(hue, saturation, brightness);
+        self.set_hsv(hue, saturation, brightness)?;
+        Ok((hue, saturation, brightness))
+    }
+
+    /// Set the hue, leaving saturation and value (brightness) unchanged
+    ///
+    /// Hue must be between 0 and 360.
+    fn set_hue(&self, hue: u16) -> Result<()> {
+        let hue = check_hue(hue)?;
+        self.update_light_state(LightStateUpdate::new().hue(hue))?;
+        Ok(())
+    }
+
+    /// Set the saturation, leaving hue and value (brightness) unchanged
+    ///
+    /// Saturation must be between 0 and 100.
+    fn set_saturation(&self, saturation: u16) -> Result<()> {
+        check_saturation(saturation)?;
+        self.update_light_state(LightStateUpdate::new().saturation(saturation))?;
+        Ok(())
+    }
+
+    /// Get the colour as red, green and blue
+    fn get_rgb(&self) -> Result<(u8, u8, u8)> {
+        let (hue, saturation, brightness) = self.get_hsv()?;
+        Ok(hsv_to_rgb(hue, saturation, brightness))
+    }
+
+    /// Set the colour from red, green and blue
+    ///
+    /// The colour is converted to hue, saturation and value (brightness) before
+    /// being sent to the device.
+    fn set_rgb(&self, red: u8, green: u8, blue: u8) -> Result<()> {
+        let (hue, saturation, brightness) = rgb_to_hsv(red, green, blue);
+        self.set_hsv(hue, saturation, brightness)
+    }
+
+    /// Set the value (brightness), leaving hue and saturation unchanged
+    ///
+    /// Brightness must be between 0 and 100.
+    fn set_value(&self, brightness: u16) -> Result<()> {
+        check_brightness(brightness)?;
+        self.update_light_state(LightStateUpdate::new().brightness(brightness))?;
         Ok(())
     }
 }
@@ -319,7 +673,7 @@ pub trait Emeter: DeviceActions {
     ///
     /// This is used by other Emeter methods. It is probably not useful to end users.
     fn emeter_type(&self) -> String {
-        String::from("emeter")
+        String::from(EMETER)
     }
 
     /// Get the realtime energy usage
@@ -329,7 +683,88 @@ pub trait Emeter: DeviceActions {
             self.emeter_type(): {"get_realtime": null}
         })
         .to_string();
-        self.send(&command)
+        let value = self.send(&command)?;
+        check_section_error(&value, &self.emeter_type())?;
+        Ok(value)
+    }
+
+    /// Get the realtime energy usage as a typed reading
+    ///
+    /// Bulbs and plugs report different readings, use the
+    /// [`EnergyReading`](../datatypes/trait.EnergyReading.html) methods to get
+    /// them in the same units.
+    ///
+    /// ```no_run
+    /// use tplinker::{capabilities::Emeter, datatypes::EnergyReading, devices::LB110};
+    ///
+    /// let bulb = LB110::new("192.168.0.99:9999").unwrap();
+    /// println!("{} W", bulb.get_energy_reading().unwrap().power_watts());
+    /// ```
+    fn get_energy_reading(&self) -> Result<EnergyRealtime> {
+        let emeter_type = self.emeter_type();
+        let value = self.get_emeter_realtime()?;
+        let pointer = format!("/{}/get_realtime", emeter_type);
+        if emeter_type == COMMON_EMETER {
+            Ok(EnergyRealtime::Bulb(parse_section::<
+                SmartlifeEmeterRealtime,
+            >(&value, &pointer)?))
+        } else {
+            let realtime: PlugEmeterRealtime = parse_section(&value, &pointer)?;
+            if realtime.power.is_none() && realtime.power_mw.is_none() {
+                return Err(Error::from("Energy reading is missing the power"));
+            }
+            Ok(EnergyRealtime::Plug(realtime))
+        }
+    }
+
+    /// Read the realtime energy usage every `interval`, passing each reading to `f`
+    ///
+    /// This blocks until `f` returns `false`. A reading that fails, for example
+    /// because of a dropped packet, is skipped, but three failures in a row stop
+    /// watching with the last error. See
+    /// [`get_energy_reading`](#method.get_energy_reading).
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use tplinker::{capabilities::Emeter, datatypes::EnergyReading, devices::HS110};
+    ///
+    /// let plug = HS110::new("192.168.0.99:9999").unwrap();
+    /// let mut readings = 0;
+    /// plug.watch_realtime(Duration::from_secs(1), |realtime| {
+    ///     println!("{} W", realtime.power_watts());
+    ///     readings += 1;
+    ///     readings < 60
+    /// })
+    /// .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the device does not have an energy meter, or if
+    /// readings keep failing.
+    fn watch_realtime<F>(&self, interval: Duration, mut f: F) -> Result<()>
+    where
+        F: FnMut(EnergyRealtime) -> bool,
+    {
+        let mut failures = 0;
+        loop {
+            match self.get_energy_reading() {
+                Ok(realtime) => {
+                    failures = 0;
+                    if !f(realtime) {
+                        return Ok(());
+                    }
+                }
+                Err(err) if err.is_unsupported() => return Err(err),
+                Err(err) => {
+                    failures += 1;
+                    if failures >= WATCH_MAX_FAILURES {
+                        return Err(err);
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
     }
 
     /// Get the daily energy usage for a given month
@@ -342,7 +777,9 @@ pub trait Emeter: DeviceActions {
             self.emeter_type(): {"get_daystat": {"month": month, "year": year}}
         })
         .to_string();
-        self.send(&command)
+        let value = self.send(&command)?;
+        check_section_error(&value, &self.emeter_type())?;
+        Ok(value)
     }
 
     /// Get the monthly energy usage for a given year
@@ -352,81 +789,623 @@ pub trait Emeter: DeviceActions {
             self.emeter_type(): {"get_monthstat": {"year": year}}
         })
         .to_string();
-        self.send(&command)
+        let value = self.send(&command)?;
+        check_section_error(&value, &self.emeter_type())?;
+        Ok(value)
     }
-}
 
-/// Check the error code of a standard command
-fn check_command_error(value: &serde_json::Value, pointer: &str) -> Result<()> {
-    if let Some(err_code) = value.pointer(pointer) {
-        if err_code == 0 {
-            Ok(())
-        } else {
-            Err(Error::from(format!("Invalid error code {}", err_code)))
-        }
-    } else {
-        Err(Error::from(format!("Invalid response format: {}", value)))
+    /// Get the daily energy usage for the current month
+    ///
+    /// The month is read from the device clock, falling back to the system
+    /// clock in UTC if that fails.
+    fn get_emeter_daily_current_month(&self) -> Result<serde_json::Value>
+    where
+        Self: Time,
+    {
+        let today = today(self)?;
+        self.get_emeter_daily(today.year, today.month)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::datatypes::tests::{HS100_JSON_OFF, HS100_JSON_ON, LB110_JSON_ON, LB120_JSON};
-    use std::cell::Cell;
 
-    struct DummyDevice {
-        msgs: Cell<Vec<String>>,
-        resps: Cell<Vec<Result<String>>>,
+    /// Get the monthly energy usage for the current year
+    ///
+    /// The year is read from the device clock, falling back to the system
+    /// clock in UTC if that fails.
+    fn get_emeter_monthly_current_year(&self) -> Result<serde_json::Value>
+    where
+        Self: Time,
+    {
+        self.get_emeter_monthly(today(self)?.year)
     }
 
-    impl DummyDevice {
-        fn new(resp: Result<String>) -> Self {
-            Self::multi(vec![resp])
-        }
-
-        fn multi(resps: Vec<Result<String>>) -> Self {
-            Self {
-                msgs: Cell::new(Vec::new()),
-                resps: Cell::new(resps),
-            }
-        }
-
-        fn push_msg(&self, msg: &str) {
-            let mut msgs = self.msgs.replace(vec![]);
-            msgs.push(msg.to_string());
-            self.msgs.set(msgs);
-        }
+    /// Get the voltage and current gain calibration of the energy meter
+    fn get_emeter_gain(&self) -> Result<EmeterGain> {
+        let command = json!({
+            self.emeter_type(): {"get_vgain_igain": null}
+        })
+        .to_string();
+        let value = self.send(&command)?;
+        check_section_error(&value, &self.emeter_type())?;
+        parse_section(&value, &format!("/{}/get_vgain_igain", self.emeter_type()))
+    }
 
-        fn pop_resp(&self) -> Result<String> {
-            let mut resps = self.resps.replace(vec![]);
-            let resp = resps.remove(0);
-            self.resps.set(resps);
-            resp
-        }
+    /// Set the voltage and current gain calibration of the energy meter
+    ///
+    /// **Warning:** the gains are used to turn raw readings into volts and amps.
+    /// Wrong values make every later reading wrong and the factory values cannot
+    /// be restored other than by writing them back, so read them with
+    /// [`get_emeter_gain`](#method.get_emeter_gain) and keep them first. Only
+    /// use this when calibrating against a trusted reference meter.
+    ///
+    /// This requires the `calibration` feature.
+    #[cfg(any(test, feature = "calibration"))]
+    fn set_emeter_gain(&self, vgain: u32, igain: u32) -> Result<()> {
+        let command = json!({
+            self.emeter_type(): {"set_vgain_igain": {"vgain": vgain, "igain": igain}}
+        })
+        .to_string();
+        let value = self.send(&command)?;
+        check_section_error(&value, &self.emeter_type())?;
+        check_command_error(
+            &value,
+            &format!("/{}/set_vgain_igain/err_code", self.emeter_type()),
+        )
     }
 
-    impl DeviceActions for DummyDevice {
-        fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
-            self.push_msg(msg);
-            match self.pop_resp() {
-                Ok(resp) => Ok(serde_json::from_str::<D>(&resp)?),
-                Err(err) => Err(err),
-            }
+    /// Get the over current, voltage and power protection cutoffs
+    ///
+    /// Some firmware reports these alongside the realtime energy usage. Returns
+    /// `Error::Unsupported` if the device does not.
+    fn get_protection_thresholds(&self) -> Result<ProtectionThresholds> {
+        let thresholds: ProtectionThresholds = parse_section(
+            &self.get_emeter_realtime()?,
+            &format!("/{}/get_realtime", self.emeter_type()),
+        )?;
+        if thresholds.is_empty() {
+            Err(Error::Unsupported(String::from(
+                "device does not report protection thresholds",
+            )))
+        } else {
+            Ok(thresholds)
         }
     }
 
-    impl Switch for DummyDevice {}
-    impl Light for DummyDevice {}
-    impl Dimmer for DummyDevice {}
-    impl ColorTemperature for DummyDevice {}
-    impl Emeter for DummyDevice {}
-
-    #[test]
-    fn device_sysinfo() {
-        let device = DummyDevice::new(Ok(HS100_JSON_OFF.to_string()));
+    /// Erase all energy usage statistics, resetting the total to zero
+    fn erase_stats(&self) -> Result<()> {
+        let command = json!({
+            self.emeter_type(): {"erase_emeter_stat": null}
+        })
+        .to_string();
+        check_command_error(
+            &self.send(&command)?,
+            &format!("/{}/erase_emeter_stat/err_code", self.emeter_type()),
+        )
+    }
+}
 
-        device.sysinfo().unwrap();
+/// Power strips with energy usage tracking on each outlet
+///
+/// This is supported by power strips like the HS300
+pub trait MultiEmeter: DeviceActions {
+    /// Get the realtime energy usage of the specified outlet
+    fn get_outlet_emeter_realtime(&self, index: usize) -> Result<EmeterRealtime> {
+        let id = outlet_id(&self.sysinfo()?, index);
+        let command = json!({
+            "context": {"child_ids": [id]},
+            "emeter": {"get_realtime": null}
+        })
+        .to_string();
+        self.send::<GetEmeterRealtimeResult>(&command)?.realtime()
+    }
+}
+
+/// Devices with a clock
+///
+/// All devices support this trait.
+pub trait Time: DeviceActions {
+    /// Type of the time service
+    ///
+    /// This is used by other Time methods. It is probably not useful to end users.
+    fn time_type(&self) -> String {
+        String::from("time")
+    }
+
+    /// Get the current time of the device clock
+    fn get_time(&self) -> Result<DeviceTime> {
+        let command = json!({
+            self.time_type(): {"get_time": null}
+        })
+        .to_string();
+        parse_section(
+            &self.send(&command)?,
+            &format!("/{}/get_time", self.time_type()),
+        )
+    }
+
+    /// Get the index of the timezone of the device
+    fn get_timezone(&self) -> Result<u32> {
+        let command = json!({
+            self.time_type(): {"get_timezone": null}
+        })
+        .to_string();
+        let timezone: Timezone = parse_section(
+            &self.send(&command)?,
+            &format!("/{}/get_timezone", self.time_type()),
+        )?;
+        Ok(timezone.index)
+    }
+
+    /// Set the device clock and timezone index
+    ///
+    /// The time is the local time in the given timezone.
+    fn set_time(&self, time: &DeviceTime, index: u32) -> Result<()> {
+        let command = json!({
+            self.time_type(): {"set_timezone": {
+                "year": time.year,
+                "month": time.month,
+                "mday": time.mday,
+                "hour": time.hour,
+                "min": time.min,
+                "sec": time.sec,
+                "index": index,
+            }}
+        })
+        .to_string();
+        check_command_error(
+            &self.send(&command)?,
+            &format!("/{}/set_timezone/err_code", self.time_type()),
+        )
+    }
+
+    /// Set the timezone index, setting the device clock to the current time in
+    /// that timezone
+    ///
+    /// `utc_offset` is the offset of the timezone in seconds east of UTC, the
+    /// device doesn't report the offset of its timezone indexes.
+    fn set_timezone(&self, index: u32, utc_offset: i32) -> Result<()> {
+        self.set_time(
+            &DeviceTime::from_system_time_with_offset(SystemTime::now(), utc_offset)?,
+            index,
+        )
+    }
+
+    /// Set the device clock to the current time, keeping its timezone index
+    ///
+    /// `utc_offset` is the offset of the device timezone in seconds east of UTC.
+    fn set_time_to_now(&self, utc_offset: i32) -> Result<()> {
+        self.set_timezone(self.get_timezone()?, utc_offset)
+    }
+}
+
+/// The current date from the device clock, or the system clock if that fails
+fn today<T: Time + ?Sized>(device: &T) -> Result<DeviceTime> {
+    device
+        .get_time()
+        .or_else(|_| DeviceTime::from_system_time(SystemTime::now()))
+}
+
+/// Devices that can randomly switch on and off to simulate occupancy
+///
+/// Smart plugs like the HS100 and HS110 support this trait.
+pub trait AntiTheft: DeviceActions {
+    /// Get the anti-theft rules
+    fn get_antitheft_rules(&self) -> Result<AntiTheftRules> {
+        parse_section(
+            &self.send(r#"{"anti_theft":{"get_rules":null}}"#)?,
+            "/anti_theft/get_rules",
+        )
+    }
+
+    /// Add an anti-theft rule and return its id
+    ///
+    /// The start and end times are offsets from midnight, and `days` is the set of
+    /// days of the week the rule applies to, starting on Sunday.
+    fn add_antitheft_rule(
+        &self,
+        start: Duration,
+        end: Duration,
+        days: [bool; 7],
+    ) -> Result<String> {
+        let (smin, emin) = (start.as_secs() / 60, end.as_secs() / 60);
+        if smin >= 24 * 60 || emin >= 24 * 60 {
+            return Err(Error::from("Start and end must be within a day"));
+        }
+        let wday = days.iter().map(|&day| u8::from(day)).collect::<Vec<_>>();
+        let command = json!({
+            "anti_theft": {"add_rule": {
+                "name": "tplinker",
+                "enable": 1,
+                "wday": wday,
+                "repeat": 1,
+                "stime_opt": 0,
+                "smin": smin,
+                "etime_opt": 0,
+                "emin": emin,
+                "eact": -1,
+                "frequency": 5,
+                "duration": 2,
+                "lastfor": 1,
+            }}
+        })
+        .to_string();
+        let result: AddRuleResult = parse_section(&self.send(&command)?, "/anti_theft/add_rule")?;
+        Ok(result.id)
+    }
+
+    /// Delete all anti-theft rules
+    fn delete_all_antitheft_rules(&self) -> Result<()> {
+        check_command_error(
+            &self.send(r#"{"anti_theft":{"delete_all_rules":null}}"#)?,
+            "/anti_theft/delete_all_rules/err_code",
+        )
+    }
+}
+
+/// Devices that can be bound to the TPLink cloud
+///
+/// All devices support this trait. Commands are sent to the device on the local
+/// network, no requests are made to the TPLink cloud directly.
+pub trait Cloud: DeviceActions {
+    /// Type of the cloud service
+    ///
+    /// This is used by other Cloud methods. It is probably not useful to end users.
+    fn cloud_type(&self) -> String {
+        String::from("cnCloud")
+    }
+
+    /// Get the cloud server, binding and connection status
+    fn get_cloud_info(&self) -> Result<CloudInfo> {
+        let command = json!({
+            self.cloud_type(): {"get_info": null}
+        })
+        .to_string();
+        parse_section(
+            &self.send(&command)?,
+            &format!("/{}/get_info", self.cloud_type()),
+        )
+    }
+
+    /// Unbind the device from its TPLink cloud account
+    fn unbind_cloud(&self) -> Result<()> {
+        let command = json!({
+            self.cloud_type(): {"unbind": null}
+        })
+        .to_string();
+        check_command_error(
+            &self.send(&command)?,
+            &format!("/{}/unbind/err_code", self.cloud_type()),
+        )
+    }
+}
+
+/// Devices that can be configured to switch on or off when power is restored
+///
+/// Smart plugs like the HS100 and HS110 support this trait, although only on newer
+/// firmware. Older firmware returns `Error::Unsupported`.
+pub trait PowerOnBehavior: DeviceActions {
+    /// Get what the relay does when power is restored
+    fn get_power_on_behavior(&self) -> Result<Behavior> {
+        let result: PowerOnBehaviorResult = parse_section(
+            &self.send(r#"{"system":{"get_power_on_behavior":null}}"#)?,
+            "/system/get_power_on_behavior",
+        )
+        .map_err(unsupported)?;
+        Ok(result.mode)
+    }
+
+    /// Set what the relay does when power is restored
+    fn set_power_on_behavior(&self, behavior: Behavior) -> Result<()> {
+        let command = json!({
+            "system": {"set_power_on_behavior": {"mode": behavior}}
+        })
+        .to_string();
+        check_command_error(
+            &self.send(&command)?,
+            "/system/set_power_on_behavior/err_code",
+        )
+        .map_err(unsupported)
+    }
+}
+
+/// Dimmer switches with configurable fading and minimum brightness
+///
+/// This is supported by wall dimmers like the HS220, see
+/// [`Device::try_dimmer_config`](../devices/enum.Device.html#method.try_dimmer_config).
+/// Other devices return `Error::Unsupported`.
+pub trait DimmerConfig: DeviceActions {
+    /// Get the fade times and minimum brightness
+    fn get_dimmer_parameters(&self) -> Result<DimmerParameters> {
+        let value = self.send(r#"{"smartlife.iot.dimmer":{"get_dimmer_parameters":null}}"#)?;
+        check_section_error(&value, DIMMER_SERVICE).map_err(unsupported)?;
+        parse_section(&value, "/smartlife.iot.dimmer/get_dimmer_parameters").map_err(unsupported)
+    }
+
+    /// Set how long switching on fades over
+    fn set_fade_on_time(&self, fade_time: Duration) -> Result<()> {
+        self.set_dimmer_parameter(
+            "set_fade_on_time",
+            json!({ "fadeTime": transition_period(fade_time) }),
+        )
+    }
+
+    /// Set how long switching off fades over
+    fn set_fade_off_time(&self, fade_time: Duration) -> Result<()> {
+        self.set_dimmer_parameter(
+            "set_fade_off_time",
+            json!({ "fadeTime": transition_period(fade_time) }),
+        )
+    }
+
+    /// Set the lowest brightness the dimmer will go down to
+    fn set_min_threshold(&self, min_threshold: u16) -> Result<()> {
+        check_brightness(min_threshold)?;
+        self.set_dimmer_parameter(
+            "set_min_threshold",
+            json!({ "minThreshold": min_threshold }),
+        )
+    }
+
+    /// Send a command to the dimmer service
+    ///
+    /// This is used by other DimmerConfig methods. It is probably not useful to end users.
+    fn set_dimmer_parameter(&self, method: &str, args: serde_json::Value) -> Result<()> {
+        let command = json!({ DIMMER_SERVICE: { method: args } }).to_string();
+        let value = self.send(&command)?;
+        check_section_error(&value, DIMMER_SERVICE).map_err(unsupported)?;
+        check_command_error(&value, &format!("/{}/{}/err_code", DIMMER_SERVICE, method))
+            .map_err(unsupported)
+    }
+}
+
+/// Namespace of the dimmer service
+const DIMMER_SERVICE: &str = "smartlife.iot.dimmer";
+
+/// Failed readings in a row after which `Emeter::watch_realtime` gives up
+const WATCH_MAX_FAILURES: u32 = 3;
+
+/// Devices with updatable firmware
+///
+/// All devices support this trait.
+pub trait Firmware: Cloud {
+    /// Get the current firmware version and any newer firmware available
+    ///
+    /// The device asks the TPLink cloud for available firmware, so this will fail
+    /// if the device is not connected to the internet.
+    fn check_firmware(&self) -> Result<FirmwareInfo> {
+        let sysinfo = self.sysinfo()?;
+        let command = json!({
+            self.cloud_type(): {"get_intl_fw_list": null}
+        })
+        .to_string();
+        let list: FirmwareList = parse_section(
+            &self.send(&command)?,
+            &format!("/{}/get_intl_fw_list", self.cloud_type()),
+        )?;
+        Ok(FirmwareInfo {
+            sw_ver: sysinfo.sw_ver,
+            hw_ver: sysinfo.hw_ver,
+            available: list.fw_list,
+        })
+    }
+
+    /// Start downloading and installing firmware from the given URL
+    ///
+    /// The URL would normally come from [`check_firmware`](#method.check_firmware).
+    /// The device will reboot once the update has been installed.
+    fn start_firmware_update(&self, url: &str) -> Result<()> {
+        let command = json!({
+            "system": {"download_firmware": {"url": url}}
+        })
+        .to_string();
+        check_command_error(&self.send(&command)?, "/system/download_firmware/err_code")
+    }
+}
+
+/// Work out the type of the emeter from the hardware type, or by trying both types
+///
+/// See [`RawDevice::detect_emeter_type`](../devices/struct.RawDevice.html#method.detect_emeter_type).
+pub(crate) fn detect_emeter_type<T: DeviceActions + ?Sized>(device: &T) -> Result<&'static str> {
+    if let Some(emeter_type) = device.sysinfo()?.emeter_type() {
+        return Ok(emeter_type);
+    }
+    for emeter_type in &[EMETER, COMMON_EMETER] {
+        let command = json!({ *emeter_type: {"get_realtime": null} }).to_string();
+        match check_section_error(&device.send(&command)?, emeter_type) {
+            Ok(()) => return Ok(emeter_type),
+            Err(err) if err.is_unsupported() => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(Error::Unsupported(String::from("no emeter found")))
+}
+
+/// Build the child id used to address a single outlet of a power strip
+fn outlet_id(sysinfo: &SysInfo, index: usize) -> String {
+    format!("{}{:0>2}", sysinfo.device_id, index)
+}
+
+/// Find the outlet of a power strip addressed by [`outlet_id`]
+///
+/// Some firmware reports only the two digit suffix of the child id.
+pub(crate) fn find_outlet(sysinfo: &SysInfo, index: usize) -> Result<&SysInfoChild> {
+    let children = sysinfo
+        .children
+        .as_ref()
+        .ok_or_else(|| Error::from("No relay state"))?;
+    let id = outlet_id(sysinfo, index);
+    children
+        .iter()
+        .find(|child| child.id == id || (child.id.len() == 2 && id.ends_with(&child.id)))
+        .ok_or_else(|| Error::from("Invalid outlet index"))
+}
+
+/// Check the state read back after a switch command, see [`Switch::switch_on_verified`]
+fn verify_switch(result: Result<()>, is_on: bool, expected: bool) -> Result<()> {
+    if is_on == expected {
+        Ok(())
+    } else {
+        result?;
+        Err(Error::from(if expected {
+            "Device did not switch on"
+        } else {
+            "Device did not switch off"
+        }))
+    }
+}
+
+/// Check the error code reported for a whole section of a response, if any
+///
+/// Devices report unsupported modules with an error code on the section itself,
+/// rather than on the command within it.
+fn check_section_error(value: &serde_json::Value, section: &str) -> Result<()> {
+    let pointer = format!("/{}/err_code", section);
+    if value.pointer(&pointer).is_some() {
+        check_command_error(value, &pointer)
+    } else {
+        Ok(())
+    }
+}
+
+/// Turn errors from the device for unsupported commands into `Error::Unsupported`
+fn unsupported(err: Error) -> Error {
+    match err {
+        Error::TPLink(err) if err.is_unsupported() => Error::Unsupported(err.err_msg),
+        err => err,
+    }
+}
+
+/// Check an alias will be accepted by the device
+fn check_alias(alias: &str) -> Result<()> {
+    if alias.len() > 31 {
+        Err(Error::from(format!(
+            "Alias must be at most 31 bytes, got {}",
+            alias.len()
+        )))
+    } else if alias.chars().any(char::is_control) {
+        Err(Error::from("Alias must not contain control characters"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check the error code of a section of a response and parse it
+fn parse_section<T: DeserializeOwned>(value: &serde_json::Value, pointer: &str) -> Result<T> {
+    check_command_error(value, &format!("{}/err_code", pointer))?;
+    let section = value
+        .pointer(pointer)
+        .ok_or_else(|| Error::from(format!("Invalid response format: {}", value)))?;
+    Ok(serde_json::from_value(section.clone())?)
+}
+
+/// Check the error code of a standard command
+///
+/// On failure the sibling `err_msg` is included in the returned error.
+fn check_command_error(value: &serde_json::Value, pointer: &str) -> Result<()> {
+    if let Some(err_code) = value.pointer(pointer) {
+        if err_code == 0 {
+            Ok(())
+        } else if let Some(err_code) = err_code.as_i64().and_then(|c| i16::try_from(c).ok()) {
+            let err_msg = pointer
+                .strip_suffix("err_code")
+                .and_then(|parent| value.pointer(&format!("{}err_msg", parent)))
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            Err(Error::from(SectionError { err_code, err_msg }))
+        } else {
+            Err(Error::from(format!("Invalid error code {}", err_code)))
+        }
+    } else {
+        Err(Error::from(format!("Invalid response format: {}", value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datatypes::tests::{
+        HS100_JSON_OFF, HS100_JSON_ON, HS110_JSON, HS300_JSON, LB110_JSON_OFF, LB110_JSON_ON,
+        LB120_JSON,
+    };
+    use crate::datatypes::EnergyReading;
+    use std::cell::Cell;
+
+    struct DummyDevice {
+        msgs: Cell<Vec<String>>,
+        resps: Cell<Vec<Result<String>>>,
+    }
+
+    impl DummyDevice {
+        fn new(resp: Result<String>) -> Self {
+            Self::multi(vec![resp])
+        }
+
+        fn multi(resps: Vec<Result<String>>) -> Self {
+            Self {
+                msgs: Cell::new(Vec::new()),
+                resps: Cell::new(resps),
+            }
+        }
+
+        fn push_msg(&self, msg: &str) {
+            let mut msgs = self.msgs.replace(vec![]);
+            msgs.push(msg.to_string());
+            self.msgs.set(msgs);
+        }
+
+        fn pop_resp(&self) -> Result<String> {
+            let mut resps = self.resps.replace(vec![]);
+            let resp = resps.remove(0);
+            self.resps.set(resps);
+            resp
+        }
+    }
+
+    impl DeviceActions for DummyDevice {
+        fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
+            self.push_msg(msg);
+            match self.pop_resp() {
+                Ok(resp) => Ok(serde_json::from_str::<D>(&resp)?),
+                Err(err) => Err(err),
+            }
+        }
+    }
+
+    impl Switch for DummyDevice {}
+    impl PowerOnBehavior for DummyDevice {}
+    impl DimmerConfig for DummyDevice {}
+    impl Light for DummyDevice {}
+    impl Dimmer for DummyDevice {}
+    impl ColorTemperature for DummyDevice {}
+    impl Colour for DummyDevice {}
+    impl Emeter for DummyDevice {}
+    impl MultiEmeter for DummyDevice {}
+    impl Time for DummyDevice {}
+    impl AntiTheft for DummyDevice {}
+    impl Cloud for DummyDevice {}
+    impl Firmware for DummyDevice {}
+
+    #[test]
+    fn device_sysinfo() {
+        let device = DummyDevice::new(Ok(HS100_JSON_OFF.to_string()));
+
+        device.sysinfo().unwrap();
+    }
+
+    #[test]
+    fn device_send_json() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_led_off":{"err_code":0}}}"#.to_string()
+        ));
+
+        let value = device
+            .send_json(&json!({"system": {"set_led_off": {"off": 1}}}))
+            .unwrap();
+
+        assert_eq!(value["system"]["set_led_off"]["err_code"], 0);
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"system":{"set_led_off":{"off":1}}}"#
+        );
     }
 
     #[test]
@@ -460,6 +1439,21 @@ mod tests {
         assert!(device.set_alias("dave").is_err());
     }
 
+    #[test]
+    fn device_set_alias_error_message() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_dev_alias":{"err_code":-3,"err_msg":"device busy"}}}"#.to_string(),
+        ));
+
+        match device.set_alias("dave") {
+            Err(Error::TPLink(err)) => {
+                assert_eq!(err.err_code, -3);
+                assert_eq!(err.err_msg, "device busy");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
     #[test]
     fn device_location() {
         let device = DummyDevice::new(Ok(HS100_JSON_OFF.to_string()));
@@ -480,139 +1474,686 @@ mod tests {
     }
 
     #[test]
-    fn device_reboot() {
-        let device = DummyDevice::new(Ok(r#"{"system":{"reboot":{"err_code":0}}}"#.to_string()));
+    fn device_factory_reset() {
+        let device = DummyDevice::new(Ok(r#"{"system":{"reset":{"err_code":0}}}"#.to_string()));
 
-        device.reboot().unwrap();
+        device.factory_reset().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![r#"{"system":{"reset":{"delay":1}}}"#]
+        );
+    }
+
+    #[test]
+    fn device_set_mac_address() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_mac_addr":{"err_code":0}}}"#.to_string()
+        ));
+
+        device
+            .set_mac_address([0x50, 0xC7, 0xBF, 0x01, 0x02, 0x0A])
+            .unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![r#"{"system":{"set_mac_addr":{"mac":"50-C7-BF-01-02-0A"}}}"#]
+        );
+    }
+
+    #[test]
+    fn device_reboot() {
+        let device = DummyDevice::new(Ok(r#"{"system":{"reboot":{"err_code":0}}}"#.to_string()));
+
+        device.reboot().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"system":{"reboot":{"delay":1}}}"#
+        );
+    }
+
+    #[test]
+    fn get_time() {
+        let device = DummyDevice::new(Ok(r#"{"time":{"get_time":{"year":2020,"month":10,"mday":4,"hour":13,"min":2,"sec":45,"err_code":0}}}"#.to_string()));
+
+        let time = device.get_time().unwrap();
+
+        assert_eq!((time.year, time.month, time.mday), (2020, 10, 4));
+        assert_eq!((time.hour, time.min, time.sec), (13, 2, 45));
+        assert_eq!(device.msgs.into_inner()[0], r#"{"time":{"get_time":null}}"#);
+    }
+
+    #[test]
+    fn set_time() {
+        let device =
+            DummyDevice::new(Ok(r#"{"time":{"set_timezone":{"err_code":0}}}"#.to_string()));
+        let time = DeviceTime {
+            year: 2020,
+            month: 10,
+            mday: 4,
+            hour: 13,
+            min: 2,
+            sec: 45,
+        };
+
+        device.set_time(&time, 39).unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"time":{"set_timezone":{"hour":13,"index":39,"mday":4,"min":2,"month":10,"sec":45,"year":2020}}}"#
+        );
+    }
+
+    #[test]
+    fn set_time_to_now() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"time":{"get_timezone":{"index":39,"err_code":0}}}"#.to_string()),
+            Ok(r#"{"time":{"set_timezone":{"err_code":0}}}"#.to_string()),
+        ]);
+
+        device.set_time_to_now(-13 * 3600).unwrap();
+
+        let msgs = device.msgs.into_inner();
+        assert_eq!(msgs[0], r#"{"time":{"get_timezone":null}}"#);
+        assert!(msgs[1].contains(r#""index":39"#));
+        let sent: serde_json::Value = serde_json::from_str(&msgs[1]).unwrap();
+        let utc_hour = DeviceTime::from_system_time(SystemTime::now())
+            .unwrap()
+            .hour;
+        let hour = sent["time"]["set_timezone"]["hour"].as_u64().unwrap();
+        // allow for the hour changing since the command was sent
+        assert!([13, 14].contains(&((u64::from(utc_hour) + 24 - hour) % 24)));
+    }
+
+    #[test]
+    fn get_antitheft_rules() {
+        let device = DummyDevice::new(Ok(r#"{"anti_theft":{"get_rules":{"rule_list":[{"id":"E36B1F4466B135C1FD481F0B4BFC9C30","name":"holiday","enable":1,"wday":[1,0,0,0,0,0,1],"stime_opt":0,"smin":1020,"etime_opt":0,"emin":1380,"repeat":1,"frequency":5,"duration":2,"lastfor":1}],"version":2,"enable":1,"err_code":0}}}"#.to_string()));
+
+        let rules = device.get_antitheft_rules().unwrap();
+
+        assert_eq!(rules.rule_list.len(), 1);
+        assert_eq!(rules.rule_list[0].smin, 1020);
+        assert_eq!(rules.rule_list[0].wday, vec![1, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn add_antitheft_rule() {
+        let device = DummyDevice::new(Ok(
+            r#"{"anti_theft":{"add_rule":{"id":"E36B1F4466B135C1FD481F0B4BFC9C30","err_code":0}}}"#
+                .to_string(),
+        ));
+
+        assert!(device
+            .add_antitheft_rule(
+                Duration::from_secs(17 * 3600),
+                Duration::from_secs(24 * 3600),
+                [true; 7]
+            )
+            .is_err());
+        let id = device
+            .add_antitheft_rule(
+                Duration::from_secs(17 * 3600),
+                Duration::from_secs(23 * 3600),
+                [true, false, false, false, false, false, true],
+            )
+            .unwrap();
+
+        assert_eq!(id, "E36B1F4466B135C1FD481F0B4BFC9C30");
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"anti_theft":{"add_rule":{"duration":2,"eact":-1,"emin":1380,"enable":1,"etime_opt":0,"frequency":5,"lastfor":1,"name":"tplinker","repeat":1,"smin":1020,"stime_opt":0,"wday":[1,0,0,0,0,0,1]}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_all_antitheft_rules() {
+        let device = DummyDevice::new(Ok(
+            r#"{"anti_theft":{"delete_all_rules":{"err_code":0}}}"#.to_string()
+        ));
+
+        device.delete_all_antitheft_rules().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"anti_theft":{"delete_all_rules":null}}"#
+        );
+    }
+
+    #[test]
+    fn get_cloud_info() {
+        let device = DummyDevice::new(Ok(r#"{"cnCloud":{"get_info":{"username":"someone@example.com","server":"devs.tplinkcloud.com","binded":1,"cld_connection":1,"illegalType":0,"stopConnect":0,"tcspStatus":1,"fwDlPage":"","tcspInfo":"","fwNotifyType":0,"err_code":0}}}"#.to_string()));
+
+        let cloud_info = device.get_cloud_info().unwrap();
+
+        assert!(cloud_info.is_bound());
+        assert!(cloud_info.is_connected());
+        assert_eq!(cloud_info.server.as_deref(), Some("devs.tplinkcloud.com"));
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"cnCloud":{"get_info":null}}"#
+        );
+    }
+
+    #[test]
+    fn unbind_cloud() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"cnCloud":{"unbind":{"err_code":0}}}"#.to_string()),
+            Ok(r#"{"cnCloud":{"unbind":{"err_code":-1,"err_msg":"not bound"}}}"#.to_string()),
+        ]);
+
+        device.unbind_cloud().unwrap();
+        assert!(device.unbind_cloud().is_err());
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"cnCloud":{"unbind":null}}"#
+        );
+    }
+
+    #[test]
+    fn check_firmware() {
+        let device = DummyDevice::multi(vec![
+            Ok(HS100_JSON_OFF.to_string()),
+            Ok(r#"{"cnCloud":{"get_intl_fw_list":{"fw_list":[{"fwType":2,"fwTitle":"Hi","fwUrl":"http://example.com/fw.bin","fwReleaseDate":"2020-01-01","fwReleaseLog":"Fixes"}],"err_code":0}}}"#.to_string()),
+        ]);
+
+        let firmware = device.check_firmware().unwrap();
+
+        assert_eq!(firmware.sw_ver, "1.5.8 Build 180815 Rel.135935");
+        assert!(firmware.is_update_available());
+        assert_eq!(
+            firmware.available[0].url.as_deref(),
+            Some("http://example.com/fw.bin")
+        );
+        assert_eq!(
+            device.msgs.into_inner()[1],
+            r#"{"cnCloud":{"get_intl_fw_list":null}}"#
+        );
+    }
+
+    #[test]
+    fn start_firmware_update() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"download_firmware":{"err_code":0}}}"#.to_string()
+        ));
+
+        device
+            .start_firmware_update("http://example.com/fw.bin")
+            .unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"system":{"download_firmware":{"url":"http://example.com/fw.bin"}}}"#
+        );
+    }
+
+    #[test]
+    fn switch_is_on_off() {
+        let device = DummyDevice::multi(vec![
+            Ok(HS100_JSON_OFF.to_string()),
+            Ok(HS100_JSON_OFF.to_string()),
+        ]);
+
+        assert!(!device.is_on().unwrap());
+        assert!(device.is_off().unwrap());
+    }
+
+    #[test]
+    fn switch_on() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()
+        ));
+
+        device.switch_on().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"system":{"set_relay_state":{"state":1}}}"#
+        );
+    }
+
+    #[test]
+    fn switch_off() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()
+        ));
+
+        device.switch_off().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"system":{"set_relay_state":{"state":0}}}"#
+        );
+    }
+
+    #[test]
+    fn switch_toggle_on() {
+        let device = DummyDevice::multi(vec![
+            Ok(HS100_JSON_OFF.to_string()),
+            Ok(r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()),
+        ]);
+
+        assert!(device.toggle().unwrap());
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"system":{"get_sysinfo":null}}"#,
+                r#"{"system":{"set_relay_state":{"state":1}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_toggle_from_skips_read() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()
+        ));
+
+        assert!(!device.toggle_from(true).unwrap());
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![r#"{"system":{"set_relay_state":{"state":0}}}"#]
+        );
+    }
+
+    #[test]
+    fn switch_toggle_off() {
+        let device = DummyDevice::multi(vec![
+            Ok(HS100_JSON_ON.to_string()),
+            Ok(r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()),
+        ]);
+
+        assert!(!device.toggle().unwrap());
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"system":{"get_sysinfo":null}}"#,
+                r#"{"system":{"set_relay_state":{"state":0}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn get_light_state() {
+        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+
+        assert_eq!(device.get_light_state().unwrap().on_off, 1);
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":null}}"#
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_light_state() {
+        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+        let set_light_state = SetLightState {
+            on_off: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(device.set_light_state(set_light_state).unwrap().on_off, 1);
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn light_mode() {
+        let device = DummyDevice::multi(vec![
+            Ok(LB110_JSON_ON.replace(r#""mode": "normal""#, r#""mode": "circadian""#)),
+            Ok(LB110_JSON_ON.to_string()),
+        ]);
+
+        assert_eq!(device.get_mode().unwrap(), LightMode::Circadian);
+        device.set_mode(LightMode::Normal).unwrap();
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":null}}"#,
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"mode":"normal"}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn light_updates_only_send_what_changes() {
+        let device = DummyDevice::multi((0..6).map(|_| Ok(LB110_JSON_ON.to_string())).collect());
+
+        device.set_brightness(50).unwrap();
+        device.set_brightness_on(60).unwrap();
+        device.set_hue(120).unwrap();
+        device.set_saturation(30).unwrap();
+        device.set_value(40).unwrap();
+        device.set_hsv(360, 100, 80).unwrap();
+
+        let sent = device
+            .msgs
+            .into_inner()
+            .into_iter()
+            .map(|msg| {
+                serde_json::from_str::<serde_json::Value>(&msg).unwrap()
+                    ["smartlife.iot.smartbulb.lightingservice"]["transition_light_state"]
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            vec![
+                r#"{"brightness":50}"#,
+                r#"{"brightness":60,"on_off":1}"#,
+                r#"{"hue":120}"#,
+                r#"{"saturation":30}"#,
+                r#"{"brightness":40}"#,
+                r#"{"brightness":80,"hue":0,"saturation":100}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn update_light_state() {
+        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+
+        device
+            .update_light_state(
+                LightStateUpdate::new()
+                    .off()
+                    .color_temp(2700)
+                    .transition(Duration::from_millis(1500)),
+            )
+            .unwrap();
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"color_temp":2700,"on_off":0,"transition_period":1500}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn update_light_state_checks_values() {
+        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+
+        assert!(device
+            .update_light_state(LightStateUpdate::new().brightness(101))
+            .is_err());
+        assert!(device
+            .update_light_state(LightStateUpdate::new().color_temp(2000))
+            .is_err());
+        assert!(device.msgs.into_inner().is_empty());
+    }
+
+    #[test]
+    fn set_light_state_err_code() {
+        let device = DummyDevice::new(Ok(
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1,"brightness":10,"hue":0,"saturation":0,"color_temp":2700,"mode":"normal","err_code":-3,"err_msg":"invalid argument"}}}"#.to_string(),
+        ));
+
+        match device.set_brightness(10) {
+            Err(Error::TPLink(err)) => {
+                assert_eq!(err.err_code, -3);
+                assert_eq!(err.err_msg, "invalid argument");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn apply_preferred_state() {
+        let device = DummyDevice::multi(vec![
+            Ok(LB110_JSON_OFF.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+            Ok(LB110_JSON_OFF.to_string()),
+        ]);
+
+        device.apply_preferred_state(2).unwrap();
+        assert!(device.apply_preferred_state(4).is_err());
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"system":{"get_sysinfo":null}}"#.to_string(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":10,"color_temp":2700,"hue":0,"on_off":1,"saturation":0}}}"#.to_string(),
+            r#"{"system":{"get_sysinfo":null}}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn brightness() {
+        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+
+        assert_eq!(device.brightness().unwrap(), 10);
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":null}}"#
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn brightness_off() {
+        let device = DummyDevice::new(Ok(LB110_JSON_OFF.to_string()));
+
+        assert_eq!(device.brightness().unwrap(), 1);
+    }
+
+    #[test]
+    fn brightness_missing_dft_on_state() {
+        let device = DummyDevice::new(Ok(
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":{"on_off":1,"err_code":0}}}"#.to_string(),
+        ));
 
-        assert_eq!(
-            device.msgs.into_inner()[0],
-            r#"{"system":{"reboot":{"delay":1}}}"#
-        );
+        assert!(device.brightness().is_err());
     }
 
     #[test]
-    fn switch_is_on_off() {
+    fn light_is_on() {
         let device = DummyDevice::multi(vec![
-            Ok(HS100_JSON_OFF.to_string()),
-            Ok(HS100_JSON_OFF.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+            Ok(LB110_JSON_OFF.to_string()),
         ]);
 
-        assert_eq!(device.is_on().unwrap(), false);
-        assert_eq!(device.is_off().unwrap(), true);
+        assert!(device.light_is_on().unwrap());
+        assert!(!device.light_is_on().unwrap());
     }
 
     #[test]
-    fn switch_on() {
+    fn get_icon() {
         let device = DummyDevice::new(Ok(
-            r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()
+            r#"{"system":{"get_dev_icon":{"icon":"aWNvbg==","hash":"abc123","err_code":0}}}"#
+                .to_string(),
         ));
 
-        device.switch_on().unwrap();
+        let icon = device.get_icon().unwrap();
 
         assert_eq!(
-            device.msgs.into_inner()[0],
-            r#"{"system":{"set_relay_state":{"state":1}}}"#
+            (icon.hash.as_str(), icon.icon.as_str()),
+            ("abc123", "aWNvbg==")
+        );
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![r#"{"system":{"get_dev_icon":null}}"#]
         );
     }
 
     #[test]
-    fn switch_off() {
+    fn set_icon() {
         let device = DummyDevice::new(Ok(
-            r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()
+            r#"{"system":{"set_dev_icon":{"err_code":0}}}"#.to_string()
         ));
 
-        device.switch_off().unwrap();
+        device.set_icon("abc123", "aWNvbg==").unwrap();
 
         assert_eq!(
-            device.msgs.into_inner()[0],
-            r#"{"system":{"set_relay_state":{"state":0}}}"#
+            device.msgs.into_inner(),
+            vec![r#"{"system":{"set_dev_icon":{"hash":"abc123","icon":"aWNvbg=="}}}"#]
         );
     }
 
     #[test]
-    fn switch_toggle_on() {
+    fn power_on_behavior() {
         let device = DummyDevice::multi(vec![
-            Ok(HS100_JSON_OFF.to_string()),
-            Ok(r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()),
+            Ok(
+                r#"{"system":{"get_power_on_behavior":{"mode":"last_status","err_code":0}}}"#
+                    .to_string(),
+            ),
+            Ok(r#"{"system":{"set_power_on_behavior":{"err_code":0}}}"#.to_string()),
         ]);
 
-        assert_eq!(device.toggle().unwrap(), true);
         assert_eq!(
-            device.msgs.into_inner(),
-            vec![
-                r#"{"system":{"get_sysinfo":null}}"#,
-                r#"{"system":{"set_relay_state":{"state":1}}}"#,
-            ]
+            device.get_power_on_behavior().unwrap(),
+            Behavior::LastStatus
+        );
+        device.set_power_on_behavior(Behavior::AlwaysOff).unwrap();
+        assert_eq!(
+            device.msgs.into_inner()[1],
+            r#"{"system":{"set_power_on_behavior":{"mode":"always_off"}}}"#
         );
     }
 
     #[test]
-    fn switch_toggle_off() {
-        let device = DummyDevice::multi(vec![
-            Ok(HS100_JSON_ON.to_string()),
-            Ok(r#"{"system":{"set_relay_state":{"err_code":0}}}"#.to_string()),
-        ]);
+    fn power_on_behavior_unsupported() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"get_power_on_behavior":{"err_code":-2,"err_msg":"method not support"}}}"#.to_string(),
+        ));
 
-        assert_eq!(device.toggle().unwrap(), false);
-        assert_eq!(
-            device.msgs.into_inner(),
-            vec![
-                r#"{"system":{"get_sysinfo":null}}"#,
-                r#"{"system":{"set_relay_state":{"state":0}}}"#,
-            ]
-        );
+        assert!(matches!(
+            device.get_power_on_behavior(),
+            Err(Error::Unsupported(_))
+        ));
     }
 
     #[test]
-    fn get_light_state() {
-        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+    fn set_alias() {
+        let device = DummyDevice::new(Ok(
+            r#"{"system":{"set_dev_alias":{"err_code":0}}}"#.to_string()
+        ));
 
-        assert_eq!(device.get_light_state().unwrap().on_off, 1);
+        assert!(device.set_alias(&"a".repeat(32)).is_err());
+        assert!(device.set_alias("Lamp\n").is_err());
+        device.set_alias("Lamp 💡").unwrap();
         assert_eq!(
             device.msgs.into_inner(),
-            vec![
-                r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":null}}"#
-                    .to_string(),
-            ]
+            vec![r#"{"system":{"set_dev_alias":{"alias":"Lamp 💡"}}}"#]
         );
     }
 
     #[test]
-    fn set_light_state() {
+    fn raw_sysinfo_json() {
+        let response = HS100_JSON_OFF
+            .replace(r#""rssi": -53"#, r#""rssi": "weak""#)
+            .replace(r#""alias": "#, r#""new_field": [1, 2], "alias": "#);
+        let device = DummyDevice::multi(vec![Ok(response.clone()), Ok(response)]);
+
+        assert!(device.sysinfo().is_err());
+        let sysinfo = device.raw_sysinfo_json().unwrap();
+        assert_eq!(sysinfo["rssi"], "weak");
+        assert_eq!(sysinfo["new_field"], serde_json::json!([1, 2]));
+        assert_eq!(sysinfo["model"], "HS100(UK)");
+    }
+
+    #[test]
+    fn device_data() {
+        let device = DummyDevice::new(Ok(HS110_JSON.to_string()));
+
+        let data = device.device_data().unwrap();
+
+        assert_eq!(data.sysinfo().model, "HS110(UK)");
+        assert!(data.emeter.is_some());
+        assert_eq!(device.msgs.into_inner(), vec![discovery::QUERY]);
+    }
+
+    #[test]
+    fn set_brightness() {
         let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
-        let mut set_light_state = SetLightState::default();
-        set_light_state.on_off = Some(1);
 
-        assert_eq!(device.set_light_state(set_light_state).unwrap().on_off, 1);
+        assert!(device.set_brightness(101).is_err());
+        device.set_brightness(56).unwrap();
         assert_eq!(device.msgs.into_inner(), vec![
-            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#.to_string(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":56}}}"#.to_string(),
         ]);
     }
 
     #[test]
-    fn brightness() {
+    fn set_brightness_on() {
+        let device = DummyDevice::new(Ok(LB110_JSON_OFF.to_string()));
+
+        assert!(device.set_brightness_on(101).is_err());
+        device.set_brightness_on(56).unwrap();
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":56,"on_off":1}}}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn set_brightness_with_transition() {
         let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
 
-        assert_eq!(device.brightness().unwrap(), 10);
+        assert!(device
+            .set_brightness_with_transition(101, Duration::from_secs(1))
+            .is_err());
+        device
+            .set_brightness_with_transition(56, Duration::from_millis(1500))
+            .unwrap();
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":56,"transition_period":1500}}}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn switch_verified() {
+        let switched = r#"{"system":{"set_relay_state":{"err_code":0}}}"#;
+        let failed = r#"{"system":{"set_relay_state":{"err_code":-3,"err_msg":"failed"}}}"#;
+        let device = DummyDevice::multi(vec![
+            Ok(switched.to_string()),
+            Ok(HS100_JSON_ON.to_string()),
+            Ok(switched.to_string()),
+            Ok(HS100_JSON_ON.to_string()),
+            Ok(failed.to_string()),
+            Ok(HS100_JSON_OFF.to_string()),
+            Ok(failed.to_string()),
+            Ok(HS100_JSON_OFF.to_string()),
+        ]);
+
+        device.switch_on_verified().unwrap();
+        let err = device.switch_off_verified().unwrap_err();
+        assert_eq!(err.to_string(), "Device did not switch off");
+        device.switch_off_verified().unwrap();
+        assert!(matches!(
+            device.switch_on_verified().unwrap_err(),
+            Error::TPLink(_)
+        ));
+    }
+
+    #[test]
+    fn fade_to() {
+        let device = DummyDevice::multi((0..4).map(|_| Ok(LB110_JSON_ON.to_string())).collect());
+
+        assert!(device.fade_to(50, Duration::from_secs(1), 0).is_err());
+        device.fade_to(40, Duration::ZERO, 3).unwrap();
         assert_eq!(
-            device.msgs.into_inner(),
-            vec![
-                r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":null}}"#
-                    .to_string(),
+            device.msgs.into_inner()[1..],
+            [
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":20,"transition_period":0}}}"#,
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":30,"transition_period":0}}}"#,
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":40,"transition_period":0}}}"#,
             ]
         );
     }
 
     #[test]
-    fn set_brightness() {
-        let device = DummyDevice::new(Ok(LB110_JSON_ON.to_string()));
+    fn get_light_details() {
+        let device = DummyDevice::new(Ok(LB120_JSON.to_string()));
 
-        assert!(device.set_brightness(101).is_err());
-        device.set_brightness(56).unwrap();
-        assert_eq!(device.msgs.into_inner(), vec![
-            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":56}}}"#.to_string(),
-        ]);
+        let details = device.get_light_details().unwrap();
+
+        assert!(details.on);
+        assert_eq!(details.color_temp, 6500);
+        assert_eq!(device.msgs.into_inner().len(), 1);
     }
 
     #[test]
@@ -634,12 +2175,66 @@ mod tests {
         let device = DummyDevice::new(Ok(LB120_JSON.to_string()));
 
         assert!(device.set_color_temp(2699).is_err());
+        assert!(device.set_color_temp(6501).is_err());
         device.set_color_temp(4500).unwrap();
         assert_eq!(device.msgs.into_inner(), vec![
             r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"color_temp":4500}}}"#.to_string(),
         ]);
     }
 
+    #[test]
+    fn set_hsv() {
+        let device = DummyDevice::multi(vec![
+            Ok(LB110_JSON_ON.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+        ]);
+
+        assert!(device.set_hsv(361, 50, 50).is_err());
+        assert!(device.set_hsv(0, 101, 50).is_err());
+        device.set_hsv(360, 50, 50).unwrap();
+        assert_eq!(device.try_set_hsv(400, 150, 120).unwrap(), (40, 100, 100));
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":50,"hue":0,"saturation":50}}}"#.to_string(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":100,"hue":40,"saturation":100}}}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn set_hue_saturation_value() {
+        let device = DummyDevice::multi(vec![
+            Ok(LB110_JSON_ON.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+        ]);
+
+        assert!(device.set_hue(361).is_err());
+        assert!(device.set_saturation(101).is_err());
+        assert!(device.set_value(101).is_err());
+        device.set_hue(120).unwrap();
+        device.set_saturation(75).unwrap();
+        device.set_value(30).unwrap();
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"hue":120}}}"#.to_string(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"saturation":75}}}"#.to_string(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":30}}}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn get_set_rgb() {
+        let device = DummyDevice::multi(vec![
+            Ok(LB110_JSON_ON.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+        ]);
+
+        assert_eq!(device.get_rgb().unwrap(), (26, 26, 26));
+        device.set_rgb(255, 0, 0).unwrap();
+        assert_eq!(device.msgs.into_inner(), vec![
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"get_light_state":null}}"#.to_string(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":100,"hue":0,"saturation":100}}}"#.to_string(),
+        ]);
+    }
+
     #[test]
     fn get_emeter_realtime() {
         let device = DummyDevice::new(Ok("{}".to_string()));
@@ -652,6 +2247,121 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_emeter_realtime_unsupported() {
+        let device = DummyDevice::new(Ok(
+            r#"{"emeter":{"err_code":-1,"err_msg":"module not support"}}"#.to_string(),
+        ));
+
+        assert!(device.get_emeter_realtime().unwrap_err().is_unsupported());
+    }
+
+    #[test]
+    fn get_energy_reading() {
+        let device = DummyDevice::new(Ok(
+            r#"{"emeter":{"get_realtime":{"current_ma":120,"voltage_mv":240000,"power_mw":2500,"total_wh":10,"err_code":0}}}"#.to_string(),
+        ));
+
+        let reading = device.get_energy_reading().unwrap();
+
+        assert!(matches!(reading, EnergyRealtime::Plug(_)));
+        assert_eq!(reading.power_watts(), 2.5);
+        assert_eq!(reading.voltage_volts(), Some(240.0));
+
+        let device = DummyDevice::new(Ok(
+            r#"{"emeter":{"get_realtime":{"voltage_mv":240000,"err_code":0}}}"#.to_string(),
+        ));
+        assert!(device.get_energy_reading().is_err());
+    }
+
+    #[test]
+    fn watch_realtime_skips_errors() {
+        let realtime = r#"{"emeter":{"get_realtime":{"current_ma":120,"voltage_mv":240000,"power_mw":2500,"total_wh":10,"err_code":0}}}"#;
+        let device = DummyDevice::multi(vec![
+            Ok(realtime.to_string()),
+            Err(Error::from("dropped")),
+            Ok(realtime.to_string()),
+            Ok(r#"{"emeter":{"err_code":-1,"err_msg":"module not support"}}"#.to_string()),
+        ]);
+
+        let mut powers = vec![];
+        device
+            .watch_realtime(Duration::ZERO, |realtime| {
+                powers.push(realtime.power_watts());
+                powers.len() < 2
+            })
+            .unwrap();
+        assert_eq!(powers, vec![2.5, 2.5]);
+
+        assert!(device
+            .watch_realtime(Duration::ZERO, |_| true)
+            .unwrap_err()
+            .is_unsupported());
+    }
+
+    #[test]
+    fn watch_realtime_stops_after_failures() {
+        let realtime = r#"{"emeter":{"get_realtime":{"current_ma":120,"voltage_mv":240000,"power_mw":2500,"total_wh":10,"err_code":0}}}"#;
+        let device = DummyDevice::multi(vec![
+            Err(Error::from("dropped")),
+            Err(Error::from("dropped")),
+            Ok(realtime.to_string()),
+            Err(Error::from("dropped")),
+            Err(Error::from("dropped")),
+            Err(Error::from("gone")),
+            Ok(realtime.to_string()),
+        ]);
+
+        let mut readings = 0;
+        let err = device
+            .watch_realtime(Duration::ZERO, |_| {
+                readings += 1;
+                true
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "gone");
+        assert_eq!(readings, 1);
+    }
+
+    #[test]
+    fn get_dimmer_parameters() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"smartlife.iot.dimmer":{"get_dimmer_parameters":{"minThreshold":5,"fadeOnTime":1000,"fadeOffTime":2000,"gentleOnTime":3000,"gentleOffTime":10000,"rampRate":30,"bulb_type":1,"err_code":0}}}"#.to_string()),
+            Ok(LB110_JSON_ON.to_string()),
+        ]);
+
+        let parameters = device.get_dimmer_parameters().unwrap();
+        assert_eq!(parameters.min_threshold, 5);
+        assert_eq!(
+            (parameters.fade_on_time, parameters.fade_off_time),
+            (1000, 2000)
+        );
+        assert_eq!(parameters.ramp_rate, Some(30));
+
+        assert!(device.get_dimmer_parameters().unwrap_err().is_unsupported());
+    }
+
+    #[test]
+    fn set_dimmer_parameters() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"smartlife.iot.dimmer":{"set_fade_on_time":{"err_code":0}}}"#.to_string()),
+            Ok(r#"{"smartlife.iot.dimmer":{"set_min_threshold":{"err_code":0}}}"#.to_string()),
+        ]);
+
+        device
+            .set_fade_on_time(Duration::from_millis(1500))
+            .unwrap();
+        assert!(device.set_min_threshold(101).is_err());
+        device.set_min_threshold(10).unwrap();
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"smartlife.iot.dimmer":{"set_fade_on_time":{"fadeTime":1500}}}"#,
+                r#"{"smartlife.iot.dimmer":{"set_min_threshold":{"minThreshold":10}}}"#,
+            ]
+        );
+    }
+
     #[test]
     fn get_emeter_daily() {
         let device = DummyDevice::new(Ok("{}".to_string()));
@@ -664,6 +2374,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_emeter_daily_current_month() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"time":{"get_time":{"year":2020,"month":10,"mday":4,"hour":13,"min":2,"sec":45,"err_code":0}}}"#.to_string()),
+            Ok("{}".to_string()),
+        ]);
+
+        device.get_emeter_daily_current_month().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[1],
+            r#"{"emeter":{"get_daystat":{"month":10,"year":2020}}}"#
+        );
+    }
+
+    #[test]
+    fn get_emeter_monthly_current_year_system_clock() {
+        let device =
+            DummyDevice::multi(vec![Err(Error::from("no response")), Ok("{}".to_string())]);
+        let year = DeviceTime::from_system_time(SystemTime::now())
+            .unwrap()
+            .year;
+
+        device.get_emeter_monthly_current_year().unwrap();
+
+        assert_eq!(
+            device.msgs.into_inner()[1],
+            format!(r#"{{"emeter":{{"get_monthstat":{{"year":{}}}}}}}"#, year)
+        );
+    }
+
     #[test]
     fn get_emeter_daily_invalid_month() {
         let device = DummyDevice::new(Ok("{}".to_string()));
@@ -682,4 +2423,107 @@ mod tests {
             vec![r#"{"emeter":{"get_monthstat":{"year":2020}}}"#,]
         );
     }
+
+    #[test]
+    fn set_emeter_gain() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"emeter":{"set_vgain_igain":{"err_code":0}}}"#.to_string()),
+            Ok(r#"{"emeter":{"err_code":-1,"err_msg":"module not support"}}"#.to_string()),
+        ]);
+
+        device.set_emeter_gain(13462, 16835).unwrap();
+        assert!(device
+            .set_emeter_gain(13462, 16835)
+            .unwrap_err()
+            .is_unsupported());
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"emeter":{"set_vgain_igain":{"igain":16835,"vgain":13462}}}"#
+        );
+    }
+
+    #[test]
+    fn get_emeter_gain() {
+        let device = DummyDevice::new(Ok(
+            r#"{"emeter":{"get_vgain_igain":{"vgain":13462,"igain":16835,"err_code":0}}}"#
+                .to_string(),
+        ));
+
+        let gain = device.get_emeter_gain().unwrap();
+
+        assert_eq!((gain.vgain, gain.igain), (13462, 16835));
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![r#"{"emeter":{"get_vgain_igain":null}}"#]
+        );
+    }
+
+    #[test]
+    fn get_protection_thresholds() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"emeter":{"get_realtime":{"current_ma":120,"voltage_mv":240000,"power_mw":2500,"total_wh":10,"over_current_ma":15000,"over_power_mw":3680000,"err_code":0}}}"#.to_string()),
+            Ok(r#"{"emeter":{"get_realtime":{"current":0.12,"voltage":240.0,"power":2.5,"total":0.01,"over_voltage":260.0,"err_code":0}}}"#.to_string()),
+            Ok(r#"{"emeter":{"get_realtime":{"current_ma":120,"voltage_mv":240000,"power_mw":2500,"total_wh":10,"err_code":0}}}"#.to_string()),
+            Ok(r#"{"emeter":{"err_code":-1,"err_msg":"module not support"}}"#.to_string()),
+        ]);
+
+        let thresholds = device.get_protection_thresholds().unwrap();
+        assert_eq!(thresholds.over_current, Some(15.0));
+        assert_eq!(thresholds.over_voltage, None);
+        assert_eq!(thresholds.over_power, Some(3680.0));
+        let thresholds = device.get_protection_thresholds().unwrap();
+        assert_eq!(thresholds.over_voltage, Some(260.0));
+        assert!(matches!(
+            device.get_protection_thresholds(),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(device
+            .get_protection_thresholds()
+            .unwrap_err()
+            .is_unsupported());
+        assert_eq!(
+            device.msgs.into_inner()[0],
+            r#"{"emeter":{"get_realtime":null}}"#
+        );
+    }
+
+    #[test]
+    fn erase_stats() {
+        let device = DummyDevice::multi(vec![
+            Ok(r#"{"emeter":{"erase_emeter_stat":{"err_code":0}}}"#.to_string()),
+            Ok(
+                r#"{"emeter":{"erase_emeter_stat":{"err_code":-3,"err_msg":"invalid argument"}}}"#
+                    .to_string(),
+            ),
+        ]);
+
+        device.erase_stats().unwrap();
+        assert!(device.erase_stats().is_err());
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"emeter":{"erase_emeter_stat":null}}"#,
+                r#"{"emeter":{"erase_emeter_stat":null}}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn get_outlet_emeter_realtime() {
+        let device = DummyDevice::multi(vec![
+            Ok(HS300_JSON.to_string()),
+            Ok(r#"{"emeter":{"get_realtime":{"voltage_mv":117379,"current_ma":120,"power_mw":13021,"total_wh":4012,"err_code":0}}}"#.to_string()),
+        ]);
+
+        let realtime = device.get_outlet_emeter_realtime(2).unwrap();
+
+        assert_eq!(realtime.power, 13021.0);
+        assert_eq!(
+            device.msgs.into_inner(),
+            vec![
+                r#"{"system":{"get_sysinfo":null}}"#,
+                r#"{"context":{"child_ids":["8006D152992421723AD993266C6EC3341B7DF5C602"]},"emeter":{"get_realtime":null}}"#,
+            ]
+        );
+    }
 }
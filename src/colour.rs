@@ -0,0 +1,94 @@
+// Conversion between RGB and the HSV representation used by colour bulbs
+//
+// Bulbs expect hue in degrees (0 to 360) with saturation and value as
+// percentages (0 to 100).
+
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::many_single_char_names
+)]
+
+pub fn rgb_to_hsv(red: u8, green: u8, blue: u8) -> (u16, u16, u16) {
+    let r = f64::from(red) / 255.0;
+    let g = f64::from(green) / 255.0;
+    let b = f64::from(blue) / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if (max - r).abs() < f64::EPSILON {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if (max - g).abs() < f64::EPSILON {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    (
+        (hue.round() as u16) % 360,
+        (saturation * 100.0).round() as u16,
+        (max * 100.0).round() as u16,
+    )
+}
+
+pub fn hsv_to_rgb(hue: u16, saturation: u16, value: u16) -> (u8, u8, u8) {
+    let h = f64::from(hue % 360) / 60.0;
+    let s = f64::from(saturation.min(100)) / 100.0;
+    let v = f64::from(value.min(100)) / 100.0;
+
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+
+    let (r, g, b) = match h as u8 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    (
+        ((r + m) * 255.0).round() as u8,
+        ((g + m) * 255.0).round() as u8,
+        ((b + m) * 255.0).round() as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primaries_round_trip() {
+        for (rgb, hsv) in &[
+            ((255, 0, 0), (0, 100, 100)),
+            ((0, 255, 0), (120, 100, 100)),
+            ((0, 0, 255), (240, 100, 100)),
+            ((255, 255, 255), (0, 0, 100)),
+            ((0, 0, 0), (0, 0, 0)),
+        ] {
+            assert_eq!(rgb_to_hsv(rgb.0, rgb.1, rgb.2), *hsv);
+            assert_eq!(hsv_to_rgb(hsv.0, hsv.1, hsv.2), *rgb);
+        }
+    }
+
+    #[test]
+    fn rgb_to_hsv_mixed() {
+        assert_eq!(rgb_to_hsv(255, 128, 0), (30, 100, 100));
+        assert_eq!(rgb_to_hsv(255, 0, 128), (330, 100, 100));
+        assert_eq!(rgb_to_hsv(64, 128, 128), (180, 50, 50));
+    }
+
+    #[test]
+    fn hsv_to_rgb_wraps_hue() {
+        assert_eq!(hsv_to_rgb(360, 100, 100), (255, 0, 0));
+        assert_eq!(hsv_to_rgb(300, 100, 100), (255, 0, 255));
+    }
+}
@@ -2,6 +2,12 @@
 
 #![allow(missing_docs)]
 
+use std::{
+    convert::TryFrom,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use crate::error::{Error, Result, SectionError};
 
 type ErrCode = i16;
@@ -10,6 +16,8 @@ pub(crate) const LIGHT_SERVICE: &str = "smartlife.iot.smartbulb.lightingservice"
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DeviceData {
+    /// Newer firmware puts system information under `smartlife.iot.common.system`
+    #[serde(alias = "smartlife.iot.common.system")]
     pub system: System,
     pub emeter: Option<SectionResult<Emeter>>,
     #[serde(flatten)]
@@ -34,12 +42,22 @@ pub enum SectionResult<T> {
 }
 
 impl<T> SectionResult<T> {
+    /// # Panics
+    ///
+    /// Panics if the section is an error. Prefer [`into_result`](#method.into_result).
     pub fn unwrap(self) -> T {
         match self {
             Self::Ok(section) => section,
             Self::Err(_) => panic!("expecting section"),
         }
     }
+
+    pub fn into_result(self) -> Result<T> {
+        match self {
+            Self::Ok(section) => Ok(section),
+            Self::Err(err) => Err(Error::from(err)),
+        }
+    }
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -50,10 +68,18 @@ pub struct GetLightStateResult {
 
 impl GetLightStateResult {
     pub fn light_state(self) -> Result<LightState> {
-        match self.lightingservice {
-            SectionResult::Ok(light_state) => Ok(light_state.light_state),
-            SectionResult::Err(err) => Err(Error::from(err)),
-        }
+        Ok(self.lightingservice.into_result()?.light_state)
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct GetEmeterRealtimeResult {
+    pub emeter: SectionResult<Emeter>,
+}
+
+impl GetEmeterRealtimeResult {
+    pub fn realtime(self) -> Result<EmeterRealtime> {
+        self.emeter.into_result()?.realtime.into_result()
     }
 }
 
@@ -104,12 +130,37 @@ pub struct SmartlifeEmeter {
     pub realtime: SmartlifeEmeterRealtime,
 }
 
+/// Realtime energy usage reported by bulbs
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SmartlifeEmeterRealtime {
     pub power_mw: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage_mv: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_ma: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_wh: Option<u32>,
     pub err_code: ErrCode,
 }
 
+impl EnergyReading for SmartlifeEmeterRealtime {
+    fn power_watts(&self) -> f64 {
+        f64::from(self.power_mw) / 1000.0
+    }
+
+    fn voltage_volts(&self) -> Option<f64> {
+        self.voltage_mv.map(|voltage| f64::from(voltage) / 1000.0)
+    }
+
+    fn current_amperes(&self) -> Option<f64> {
+        self.current_ma.map(|current| f64::from(current) / 1000.0)
+    }
+
+    fn total_kilowatt_hours(&self) -> Option<f64> {
+        self.total_wh.map(|total| f64::from(total) / 1000.0)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SmartlifeLightingService {
     #[serde(rename = "get_light_state")]
@@ -122,6 +173,11 @@ pub struct System {
     pub sysinfo: SysInfo,
 }
 
+/// Emeter namespace used by plugs and power strips
+pub(crate) const EMETER: &str = "emeter";
+/// Emeter namespace used by bulbs
+pub(crate) const COMMON_EMETER: &str = "smartlife.iot.common.emeter";
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SysInfo {
     // COMMON
@@ -156,6 +212,7 @@ pub struct SysInfo {
     pub updating: Option<u8>,
     pub icon_hash: Option<String>,
     pub led_off: Option<u8>,
+    pub next_action: Option<NextAction>,
 
     // HS100
     pub longitude_i: Option<i32>,
@@ -166,6 +223,9 @@ pub struct SysInfo {
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
 
+    // HS220
+    pub brightness: Option<u16>,
+
     // HS300
     pub children: Option<Vec<SysInfoChild>>,
     pub child_num: Option<u8>,
@@ -175,7 +235,22 @@ pub struct SysInfo {
     pub is_dimmable: Option<u8>,
     pub is_color: Option<u8>,
     pub is_variable_color_temp: Option<u8>,
+    #[serde(rename = "preferred_state")]
+    pub preferred_states: Option<Vec<PreferredState>>,
     pub heapsize: Option<u64>,
+    pub dev_state: Option<String>,
+    pub disco_ver: Option<String>,
+    pub ctrl_protocols: Option<CtrlProtocols>,
+}
+
+/// The control protocol a device speaks, reported by bulbs
+///
+/// Devices on the legacy protocol report `Linkie`; this can be used to decide
+/// which transport to use for a device.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct CtrlProtocols {
+    pub name: String,
+    pub version: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -184,24 +259,403 @@ pub struct SysInfoChild {
     pub state: u8,
     pub alias: String,
     pub on_time: u64,
+    pub next_action: Option<NextAction>,
+}
+
+impl SysInfoChild {
+    /// Whether the outlet is switched on
+    ///
+    /// See [`SysInfo::relay_is_on`](struct.SysInfo.html#method.relay_is_on).
+    pub fn is_on(&self) -> bool {
+        relay_is_on(self.state)
+    }
+
+    /// How long the outlet has been switched on for
+    pub fn uptime(&self) -> Duration {
+        Duration::from_secs(self.on_time)
+    }
 }
 
 impl SysInfo {
+    /// The wifi signal strength, displayed in dBm
+    pub fn signal(&self) -> Signal {
+        Signal(self.rssi)
+    }
+
+    /// Valid color temperature range of the model in degrees Kelvin, inclusive
+    ///
+    /// Models that are not known fall back to the 2700K to 6500K of the LB120.
+    pub fn color_temp_range(&self) -> (u16, u16) {
+        color_temp_range_for_model(&self.model)
+    }
+
+    /// A rough measure of the wifi signal strength
+    pub fn signal_quality(&self) -> SignalQuality {
+        match self.rssi {
+            rssi if rssi >= -50 => SignalQuality::Excellent,
+            rssi if rssi >= -60 => SignalQuality::Good,
+            rssi if rssi >= -70 => SignalQuality::Fair,
+            _ => SignalQuality::Weak,
+        }
+    }
+
+    /// The wifi signal strength as a percentage
+    ///
+    /// -100 dBm or lower is 0% and -50 dBm or higher is 100%.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn signal_percent(&self) -> u8 {
+        (2 * (self.rssi.clamp(-100, -50) + 100)) as u8
+    }
+
+    /// Whether the relay is switched on
+    ///
+    /// Devices are only ever sent `0` (off) or `1` (on), but some firmware reports
+    /// other values, such as `2`, while the relay is on. Any non-zero value is
+    /// treated as on. Returns `None` for devices without a relay, such as bulbs.
+    pub fn relay_is_on(&self) -> Option<bool> {
+        self.relay_state.map(relay_is_on)
+    }
+
+    /// The MAC address as bytes
+    ///
+    /// Plugs report `00:00:00:00:00:00` in `mac` and bulbs report `000000000000` in
+    /// `mic_mac`, both are accepted as are `-` separators. Returns `None` if the
+    /// address cannot be parsed.
+    pub fn mac_address(&self) -> Option<[u8; 6]> {
+        parse_mac(&self.mac)
+    }
+
+    /// The MAC address in the canonical `00:1A:2B:3C:4D:5E` format
+    ///
+    /// See [`mac_address`](#method.mac_address).
+    pub fn canonical_mac(&self) -> Option<String> {
+        self.mac_address().map(|mac| {
+            mac.iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+    }
+
+    /// Whether the relay or light is switched on
+    ///
+    /// Returns `None` for devices that report neither, such as power strips.
+    pub fn is_on(&self) -> Option<bool> {
+        self.relay_is_on().or_else(|| {
+            self.light_state
+                .as_ref()
+                .map(|light_state| light_state.on_off == 1)
+        })
+    }
+
+    /// How long the relay has been switched on for
+    ///
+    /// Returns `None` for devices that do not report an on time, such as bulbs.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.on_time
+            .and_then(|on_time| u64::try_from(on_time).ok())
+            .map(Duration::from_secs)
+    }
+
+    /// The features listed in `feature`, such as `TIM:ENE`
+    pub fn features(&self) -> Vec<Feature> {
+        self.feature
+            .as_deref()
+            .unwrap_or_default()
+            .split(':')
+            .filter(|token| !token.is_empty())
+            .map(Feature::from)
+            .collect()
+    }
+
+    /// Whether the device has the given feature
+    pub fn has_feature(&self, feature: &Feature) -> bool {
+        self.features().contains(feature)
+    }
+
+    /// The emeter namespace for this type of device, if it is known
+    ///
+    /// Bulbs use `smartlife.iot.common.emeter` and plugs use `emeter`.
+    pub fn emeter_type(&self) -> Option<&'static str> {
+        match self.hw_type.as_str() {
+            "IOT.SMARTBULB" => Some(COMMON_EMETER),
+            "IOT.SMARTPLUGSWITCH" => Some(EMETER),
+            _ => None,
+        }
+    }
+
+    /// The region the device was made for, from the end of the model, for example `UK` for `HS110(UK)`
+    ///
+    /// This tells whether the device expects 120V or 230V mains. Returns `None`
+    /// if the model has no region.
+    pub fn region(&self) -> Option<String> {
+        let model = self.model.trim();
+        let region = model.strip_suffix(')')?.rsplit_once('(')?.1.trim();
+        if region.is_empty() {
+            None
+        } else {
+            Some(region.to_uppercase())
+        }
+    }
+
+    /// Whether the device is in the middle of a firmware update
+    ///
+    /// Commands sent during an update can leave the device unusable, see
+    /// [`RawDevice::refuse_while_updating`](../devices/struct.RawDevice.html#method.refuse_while_updating).
+    pub fn is_updating(&self) -> bool {
+        self.updating.unwrap_or(0) != 0
+    }
+
+    /// The next scheduled action, `NextAction::None` if there is none
+    pub fn next_action(&self) -> NextAction {
+        self.next_action.unwrap_or(NextAction::None)
+    }
+
+    /// A snapshot of the device's health for monitoring
+    pub fn health(&self) -> DeviceHealth {
+        DeviceHealth {
+            free_heap: self.heapsize,
+            updating: self.is_updating(),
+            err_code: self.err_code,
+            ntc_state: self.ntc_state,
+            rssi: self.rssi,
+        }
+    }
+
     pub fn is_dimmable(&self) -> bool {
-        self.is_dimmable
-            .map_or(false, |is_dimmable| is_dimmable == 1)
+        self.is_dimmable == Some(1)
     }
 
     pub fn is_variable_color_temp(&self) -> bool {
-        self.is_variable_color_temp
-            .map_or(false, |is_variable_color_temp| is_variable_color_temp == 1)
+        self.is_variable_color_temp == Some(1)
     }
 
     pub fn is_color(&self) -> bool {
-        self.is_color.map_or(false, |is_color| is_color == 1)
+        self.is_color == Some(1)
+    }
+}
+
+/// A feature flag from system information
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub enum Feature {
+    /// Timers and schedules, `TIM`
+    Timer,
+    /// Energy meter, `ENE`
+    Energy,
+    /// A feature not known to this library
+    Other(String),
+}
+
+impl From<&str> for Feature {
+    fn from(token: &str) -> Self {
+        match token {
+            "TIM" => Feature::Timer,
+            "ENE" => Feature::Energy,
+            other => Feature::Other(other.to_string()),
+        }
     }
 }
 
+/// The next action from the device schedule
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(from = "RawNextAction", into = "RawNextAction")]
+pub enum NextAction {
+    /// Nothing is scheduled
+    None,
+    /// The device will switch on at the given time after midnight
+    TurnOn { at: Duration },
+    /// The device will switch off at the given time after midnight
+    TurnOff { at: Duration },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+struct RawNextAction {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<i8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schd_sec: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<u8>,
+}
+
+impl From<RawNextAction> for NextAction {
+    fn from(raw: RawNextAction) -> Self {
+        match (raw.kind, raw.schd_sec, raw.action) {
+            (Some(-1), _, _) | (_, None, _) | (_, _, None) => NextAction::None,
+            (_, Some(secs), Some(0)) => NextAction::TurnOff {
+                at: Duration::from_secs(secs),
+            },
+            (_, Some(secs), Some(_)) => NextAction::TurnOn {
+                at: Duration::from_secs(secs),
+            },
+        }
+    }
+}
+
+impl From<NextAction> for RawNextAction {
+    fn from(next_action: NextAction) -> Self {
+        let (schd_sec, action) = match next_action {
+            NextAction::None => {
+                return RawNextAction {
+                    kind: Some(-1),
+                    schd_sec: None,
+                    action: None,
+                }
+            }
+            NextAction::TurnOn { at } => (at.as_secs(), 1),
+            NextAction::TurnOff { at } => (at.as_secs(), 0),
+        };
+        RawNextAction {
+            kind: Some(1),
+            schd_sec: Some(schd_sec),
+            action: Some(action),
+        }
+    }
+}
+
+/// Health diagnostics reported in system information
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceHealth {
+    /// Free heap in bytes, only reported by bulbs
+    pub free_heap: Option<u64>,
+    /// Whether a firmware update is in progress
+    pub updating: bool,
+    /// Error code of the system information, zero if there is no error
+    pub err_code: ErrCode,
+    /// Thermistor state, only reported by some plugs
+    pub ntc_state: Option<u8>,
+    /// Wifi signal strength in dBm
+    pub rssi: i32,
+}
+
+/// Wifi signal strength in dBm, see [`SysInfo::signal`](struct.SysInfo.html#method.signal)
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct Signal(pub i32);
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} dBm", self.0)
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum SignalQuality {
+    /// -50 dBm or higher
+    Excellent,
+    /// -60 dBm to -51 dBm
+    Good,
+    /// -70 dBm to -61 dBm
+    Fair,
+    /// Lower than -70 dBm
+    Weak,
+}
+
+/// Notices when a device's uptime goes backwards between polls
+///
+/// Pass the system information from each poll to [`update`](#method.update).
+/// The uptime is how long the relay has been on, so it only counts while the
+/// relay is on; switching the device off and on again between two polls looks
+/// the same as a reboot.
+///
+/// ```no_run
+/// use std::{thread, time::Duration};
+/// use tplinker::{capabilities::DeviceActions, datatypes::RebootDetector, devices::HS110};
+///
+/// let device = HS110::new("192.168.0.99:9999").unwrap();
+/// let mut detector = RebootDetector::new();
+/// loop {
+///     if let Some(rebooted) = detector.update(&device.sysinfo().unwrap()) {
+///         println!("rebooted, was up for {:?}", rebooted.previous);
+///     }
+///     thread::sleep(Duration::from_secs(60));
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RebootDetector {
+    last: Option<Duration>,
+}
+
+/// The uptimes either side of a reboot, see [`RebootDetector`](struct.RebootDetector.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rebooted {
+    /// The uptime at the previous poll
+    pub previous: Duration,
+    /// The uptime now
+    pub current: Duration,
+}
+
+impl RebootDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the latest uptime, returning `Some` if it is lower than the last one
+    ///
+    /// Nothing is recorded while the relay is off or the uptime is unknown.
+    pub fn update(&mut self, sysinfo: &SysInfo) -> Option<Rebooted> {
+        let current = match (sysinfo.relay_is_on(), sysinfo.uptime()) {
+            (Some(true), Some(uptime)) => uptime,
+            _ => {
+                self.last = None;
+                return None;
+            }
+        };
+        let previous = self.last.replace(current)?;
+        if current < previous {
+            Some(Rebooted { previous, current })
+        } else {
+            None
+        }
+    }
+}
+
+/// A one line summary of the alias, model, on state and signal strength
+///
+/// For example `Switch One (HS110(UK)) on, signal -40 dBm`.
+impl fmt::Display for SysInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.is_on() {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "unknown",
+        };
+        write!(
+            f,
+            "{} ({}) {}, signal {}",
+            self.alias,
+            self.model,
+            state,
+            self.signal()
+        )
+    }
+}
+
+/// The same summary as the system information
+impl fmt::Display for DeviceData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.sysinfo().fmt(f)
+    }
+}
+
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let digits = mac
+        .chars()
+        .filter(|c| *c != ':' && *c != '-')
+        .collect::<String>();
+    if digits.len() != 12 || !digits.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0_u8; 6];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn relay_is_on(relay_state: u8) -> bool {
+    relay_state != 0
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LightState {
     pub on_off: u8,
@@ -210,29 +664,158 @@ pub struct LightState {
     #[serde(flatten)]
     dft_on_on_state: Option<DftOnState>,
     pub err_code: Option<ErrCode>,
+    pub err_msg: Option<String>,
 }
 
 impl LightState {
+    /// Turn a non-zero `err_code` into an error
+    ///
+    /// Bulbs can reject a change while still sending back the light state, in
+    /// which case the error is only in the light state itself.
+    pub fn check_err_code(self) -> Result<Self> {
+        match self.err_code {
+            Some(err_code) if err_code != 0 => Err(Error::from(SectionError {
+                err_code,
+                err_msg: self.err_msg.unwrap_or_default(),
+            })),
+            _ => Ok(self),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the default on state is missing. Prefer
+    /// [`try_dft_on_state`](#method.try_dft_on_state).
     pub fn dft_on_state(&self) -> &DftOnState {
-        if let Some(dft_on_state) = &self.dft_off_on_state {
-            dft_on_state
-        } else if let Some(dft_on_state) = &self.dft_on_on_state {
-            dft_on_state
+        self.try_dft_on_state()
+            .expect("dft_on_state must be present in some way")
+    }
+
+    pub fn try_dft_on_state(&self) -> Option<&DftOnState> {
+        self.dft_off_on_state
+            .as_ref()
+            .or(self.dft_on_on_state.as_ref())
+    }
+
+    /// The state the light is showing now, or will show when switched on
+    ///
+    /// When the light is on this prefers the live state, otherwise it prefers the
+    /// saved default on state.
+    pub fn current_state(&self) -> Option<&DftOnState> {
+        if self.on_off == 1 {
+            self.dft_on_on_state
+                .as_ref()
+                .or(self.dft_off_on_state.as_ref())
         } else {
-            panic!("dft_on_state must be present in some way");
+            self.try_dft_on_state()
         }
     }
 }
 
+/// How a bulb picks its brightness and colour
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum LightMode {
+    /// Brightness and colour are set by hand
+    Normal,
+    /// Brightness and colour temperature follow the time of day, overriding
+    /// changes made by hand
+    Circadian,
+    /// A mode not known to this library
+    Unknown(String),
+}
+
+impl LightMode {
+    /// The mode as sent to and from the bulb, for example `normal`
+    pub fn as_str(&self) -> &str {
+        match self {
+            LightMode::Normal => "normal",
+            LightMode::Circadian => "circadian",
+            LightMode::Unknown(mode) => mode,
+        }
+    }
+}
+
+impl From<String> for LightMode {
+    fn from(mode: String) -> Self {
+        match mode.as_str() {
+            "normal" => LightMode::Normal,
+            "circadian" => LightMode::Circadian,
+            _ => LightMode::Unknown(mode),
+        }
+    }
+}
+
+impl From<LightMode> for String {
+    fn from(mode: LightMode) -> Self {
+        match mode {
+            LightMode::Unknown(mode) => mode,
+            known => known.as_str().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LightMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DftOnState {
-    pub mode: String,
+    pub mode: LightMode,
+    pub hue: u16,
+    pub saturation: u16,
+    pub color_temp: u16,
+    pub brightness: u16,
+}
+
+/// Everything about the state of a light from a single request, see
+/// [`Light::get_light_details`](../capabilities/trait.Light.html#method.get_light_details)
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct LightDetails {
+    /// Whether the light is on
+    pub on: bool,
+    /// Light mode, circadian mode overrides changes made by hand
+    pub mode: LightMode,
+    pub hue: u16,
+    pub saturation: u16,
+    /// Color temperature in degrees Kelvin, `0` when showing a colour
+    pub color_temp: u16,
+    /// Percentage brightness
+    pub brightness: u16,
+}
+
+impl LightDetails {
+    /// Details of the state the light is showing now, or will show when switched on
+    ///
+    /// Returns `None` if the light state has no default on state.
+    pub fn from_light_state(light_state: &LightState) -> Option<Self> {
+        light_state.current_state().map(|state| Self {
+            on: light_state.on_off == 1,
+            mode: state.mode.clone(),
+            hue: state.hue,
+            saturation: state.saturation,
+            color_temp: state.color_temp,
+            brightness: state.brightness,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PreferredState {
+    pub index: u8,
     pub hue: u16,
     pub saturation: u16,
     pub color_temp: u16,
     pub brightness: u16,
 }
 
+/// The `transition_light_state` command sent to a light
+///
+/// Fields that are `None` are not sent and the light keeps its current value
+/// for them. Prefer building this with [`LightStateUpdate`](struct.LightStateUpdate.html),
+/// which checks values are in range.
 #[derive(Debug, Serialize, Clone, Default)]
 pub struct SetLightState {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -245,6 +828,224 @@ pub struct SetLightState {
     pub color_temp: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub brightness: Option<u16>,
+    /// Fade duration in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition_period: Option<u32>,
+    /// Switch between normal and circadian mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<LightMode>,
+}
+
+impl SetLightState {
+    /// Start building a light state, the same as [`LightStateUpdate::new`](struct.LightStateUpdate.html#method.new)
+    pub fn builder() -> LightStateUpdate {
+        LightStateUpdate::new()
+    }
+}
+
+/// A change to the state of a light, see [`Light::update_light_state`](../capabilities/trait.Light.html#method.update_light_state)
+///
+/// Only what is set is sent to the light. Everything else is left as it is on
+/// the device, so setting only the hue keeps the current saturation and
+/// brightness. Values are checked when the update is [`build`](#method.build)
+/// into a [`SetLightState`](struct.SetLightState.html).
+///
+/// ```
+/// use std::time::Duration;
+/// use tplinker::datatypes::LightStateUpdate;
+///
+/// let light_state = LightStateUpdate::new()
+///     .hue(120)
+///     .transition(Duration::from_secs(1))
+///     .build()
+///     .unwrap();
+/// assert_eq!(serde_json::to_string(&light_state).unwrap(), r#"{"hue":120,"transition_period":1000}"#);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LightStateUpdate {
+    on: Option<bool>,
+    hue: Option<u16>,
+    saturation: Option<u16>,
+    brightness: Option<u16>,
+    color_temp: Option<u16>,
+    mode: Option<LightMode>,
+    transition: Option<Duration>,
+}
+
+impl LightStateUpdate {
+    /// An update that leaves everything unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch the light on
+    pub fn on(mut self) -> Self {
+        self.on = Some(true);
+        self
+    }
+
+    /// Switch the light off
+    pub fn off(mut self) -> Self {
+        self.on = Some(false);
+        self
+    }
+
+    /// Hue, between 0 and 360 where 360 is the same as 0
+    pub fn hue(mut self, hue: u16) -> Self {
+        self.hue = Some(hue);
+        self
+    }
+
+    /// Percentage saturation, between 0 and 100
+    pub fn saturation(mut self, saturation: u16) -> Self {
+        self.saturation = Some(saturation);
+        self
+    }
+
+    /// Percentage brightness, between 0 and 100
+    pub fn brightness(mut self, brightness: u16) -> Self {
+        self.brightness = Some(brightness);
+        self
+    }
+
+    /// Color temperature in degrees Kelvin, between 2700 and 6500
+    ///
+    /// A color temperature of 0 switches the light to its hue and saturation.
+    pub fn color_temp(mut self, color_temp: u16) -> Self {
+        self.color_temp = Some(color_temp);
+        self
+    }
+
+    /// Switch between normal and circadian mode
+    pub fn mode(mut self, mode: LightMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Fade to the new state over the given duration
+    pub fn transition(mut self, transition: Duration) -> Self {
+        self.transition = Some(transition);
+        self
+    }
+
+    /// Build the light state, checking that all values are in range
+    ///
+    /// The color temperature is checked against the 2700K to 6500K of the LB120,
+    /// use [`build_with_color_temp_range`](#method.build_with_color_temp_range)
+    /// for other bulbs.
+    pub fn build(self) -> Result<SetLightState> {
+        self.build_with_color_temp_range(LB120_COLOR_TEMP_RANGE)
+    }
+
+    /// Build the light state, checking the color temperature against the given
+    /// inclusive range in degrees Kelvin, such as [`Light::color_temp_range`](../capabilities/trait.Light.html#method.color_temp_range)
+    pub fn build_with_color_temp_range(
+        self,
+        color_temp_range: (u16, u16),
+    ) -> Result<SetLightState> {
+        if let Some(brightness) = self.brightness {
+            check_brightness(brightness)?;
+        }
+        if let Some(saturation) = self.saturation {
+            check_saturation(saturation)?;
+        }
+        match self.color_temp {
+            Some(color_temp) if color_temp != 0 => check_color_temp(color_temp, color_temp_range)?,
+            _ => {}
+        }
+        Ok(SetLightState {
+            on_off: self.on.map(u8::from),
+            hue: self.hue.map(check_hue).transpose()?,
+            saturation: self.saturation,
+            color_temp: self.color_temp,
+            brightness: self.brightness,
+            transition_period: self.transition.map(transition_period),
+            mode: self.mode,
+        })
+    }
+}
+
+/// Check a brightness percentage is in range
+pub(crate) fn check_brightness(brightness: u16) -> Result<()> {
+    if brightness > 100 {
+        Err(Error::from("Brightness must be between 0 and 100"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Color temperature range of the LB120 in degrees Kelvin
+pub(crate) const LB120_COLOR_TEMP_RANGE: (u16, u16) = (2700, 6500);
+
+/// Color temperature ranges in degrees Kelvin by model, without the region suffix
+const COLOR_TEMP_RANGES: &[(&str, (u16, u16))] = &[
+    ("LB120", LB120_COLOR_TEMP_RANGE),
+    ("LB130", (2500, 9000)),
+    ("LB230", (2500, 9000)),
+    ("KL120", (2700, 6500)),
+    ("KL125", (2500, 6500)),
+    ("KL130", (2500, 9000)),
+    ("KL135", (2500, 6500)),
+    ("KL430", (2500, 9000)),
+];
+
+/// Color temperature range of a model such as `KL130(EU)` in degrees Kelvin,
+/// falling back to the LB120 range for models not listed
+pub(crate) fn color_temp_range_for_model(model: &str) -> (u16, u16) {
+    COLOR_TEMP_RANGES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map_or(LB120_COLOR_TEMP_RANGE, |(_, range)| *range)
+}
+
+/// Check a color temperature is in the given inclusive range
+pub(crate) fn check_color_temp(color_temp: u16, (min, max): (u16, u16)) -> Result<()> {
+    if (min..=max).contains(&color_temp) {
+        Ok(())
+    } else {
+        Err(Error::from(format!(
+            "Color temperature must be between {} and {}",
+            min, max
+        )))
+    }
+}
+
+/// Check a hue is in range, returning it with 360 wrapped to 0
+pub(crate) fn check_hue(hue: u16) -> Result<u16> {
+    if hue > 360 {
+        Err(Error::from("Hue must be between 0 and 360"))
+    } else {
+        Ok(hue % 360)
+    }
+}
+
+/// Check a saturation percentage is in range
+pub(crate) fn check_saturation(saturation: u16) -> Result<()> {
+    if saturation > 100 {
+        Err(Error::from("Saturation must be between 0 and 100"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check hue, saturation and value (brightness) are in range, returning them with
+/// a hue of 360 wrapped to 0
+pub(crate) fn check_hsv(hue: u16, saturation: u16, brightness: u16) -> Result<(u16, u16, u16)> {
+    let hue = check_hue(hue)?;
+    check_saturation(saturation)?;
+    check_brightness(brightness)?;
+    Ok((hue, saturation, brightness))
+}
+
+/// Bring hue, saturation and value (brightness) into range by wrapping the hue
+/// around the colour wheel and capping saturation and brightness at 100
+pub(crate) fn clamp_hsv(hue: u16, saturation: u16, brightness: u16) -> (u16, u16, u16) {
+    (hue % 360, saturation.min(100), brightness.min(100))
+}
+
+/// Convert a transition duration into the milliseconds expected by bulbs
+pub(crate) fn transition_period(transition: Duration) -> u32 {
+    u32::try_from(transition.as_millis()).unwrap_or(u32::MAX)
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -267,11 +1068,342 @@ pub struct EmeterRealtime {
     pub err_code: ErrCode,
 }
 
+/// Realtime energy usage reported by plugs and power strips, see
+/// [`Emeter::get_energy_reading`](../capabilities/trait.Emeter.html#method.get_energy_reading)
+///
+/// Depending on the firmware the values are either in base units or in milli
+/// units, such as `power_mw`, and are kept as reported. Use the
+/// [`EnergyReading`](trait.EnergyReading.html) methods for consistent units.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PlugEmeterRealtime {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_ma: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage_mv: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_mw: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_wh: Option<f64>,
+    pub err_code: ErrCode,
+}
+
+/// Pick a value reported in base units, or else convert one reported in milli units
+fn base_or_milli(base: Option<f64>, milli: Option<f64>) -> Option<f64> {
+    base.or_else(|| milli.map(|value| value / 1000.0))
+}
+
+impl EnergyReading for PlugEmeterRealtime {
+    fn power_watts(&self) -> f64 {
+        base_or_milli(self.power, self.power_mw).unwrap_or_default()
+    }
+
+    fn voltage_volts(&self) -> Option<f64> {
+        base_or_milli(self.voltage, self.voltage_mv)
+    }
+
+    fn current_amperes(&self) -> Option<f64> {
+        base_or_milli(self.current, self.current_ma)
+    }
+
+    fn total_kilowatt_hours(&self) -> Option<f64> {
+        base_or_milli(self.total, self.total_wh)
+    }
+}
+
+/// Energy usage in consistent units, whatever the device reports
+pub trait EnergyReading {
+    /// Power in watts
+    fn power_watts(&self) -> f64;
+    /// Voltage in volts, if reported
+    fn voltage_volts(&self) -> Option<f64>;
+    /// Current in amperes, if reported
+    fn current_amperes(&self) -> Option<f64>;
+    /// Total energy used in kilowatt hours, if reported
+    fn total_kilowatt_hours(&self) -> Option<f64>;
+}
+
+/// A realtime energy reading from either a plug or a bulb, see
+/// [`Emeter::get_energy_reading`](../capabilities/trait.Emeter.html#method.get_energy_reading)
+#[derive(Debug, Clone)]
+pub enum EnergyRealtime {
+    /// Reading from the `emeter` service of plugs and power strips
+    Plug(PlugEmeterRealtime),
+    /// Reading from the `smartlife.iot.common.emeter` service of bulbs
+    Bulb(SmartlifeEmeterRealtime),
+}
+
+impl EnergyReading for EnergyRealtime {
+    fn power_watts(&self) -> f64 {
+        match self {
+            Self::Plug(realtime) => realtime.power_watts(),
+            Self::Bulb(realtime) => realtime.power_watts(),
+        }
+    }
+
+    fn voltage_volts(&self) -> Option<f64> {
+        match self {
+            Self::Plug(realtime) => realtime.voltage_volts(),
+            Self::Bulb(realtime) => realtime.voltage_volts(),
+        }
+    }
+
+    fn current_amperes(&self) -> Option<f64> {
+        match self {
+            Self::Plug(realtime) => realtime.current_amperes(),
+            Self::Bulb(realtime) => realtime.current_amperes(),
+        }
+    }
+
+    fn total_kilowatt_hours(&self) -> Option<f64> {
+        match self {
+            Self::Plug(realtime) => realtime.total_kilowatt_hours(),
+            Self::Bulb(realtime) => realtime.total_kilowatt_hours(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmeterGain {
+    /// Voltage gain calibration
+    pub vgain: u32,
+    /// Current gain calibration
+    pub igain: u32,
+}
+
+/// Protection cutoffs configured in the energy meter
+///
+/// Depending on the firmware the device reports these in base or milli units,
+/// they are converted to amperes, volts and watts.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(from = "RawProtectionThresholds")]
+pub struct ProtectionThresholds {
+    /// Over current cutoff in amperes
+    pub over_current: Option<f64>,
+    /// Over voltage cutoff in volts
+    pub over_voltage: Option<f64>,
+    /// Over power cutoff in watts
+    pub over_power: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawProtectionThresholds {
+    over_current: Option<f64>,
+    over_current_ma: Option<f64>,
+    over_voltage: Option<f64>,
+    over_voltage_mv: Option<f64>,
+    over_power: Option<f64>,
+    over_power_mw: Option<f64>,
+}
+
+impl From<RawProtectionThresholds> for ProtectionThresholds {
+    fn from(raw: RawProtectionThresholds) -> Self {
+        Self {
+            over_current: base_or_milli(raw.over_current, raw.over_current_ma),
+            over_voltage: base_or_milli(raw.over_voltage, raw.over_voltage_mv),
+            over_power: base_or_milli(raw.over_power, raw.over_power_mw),
+        }
+    }
+}
+
+impl ProtectionThresholds {
+    /// Whether the device reported any thresholds
+    pub fn is_empty(&self) -> bool {
+        self.over_current.is_none() && self.over_voltage.is_none() && self.over_power.is_none()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DeviceTime {
+    pub year: u16,
+    pub month: u8,
+    pub mday: u8,
+    pub hour: u8,
+    pub min: u8,
+    pub sec: u8,
+}
+
+impl DeviceTime {
+    /// Convert a system time into a UTC calendar time
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub fn from_system_time(time: SystemTime) -> Result<Self> {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::from("Time is before the unix epoch"))?
+            .as_secs();
+        let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+        // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let mday = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Ok(Self {
+            year: year as u16,
+            month: month as u8,
+            mday: mday as u8,
+            hour: (secs_of_day / 3600) as u8,
+            min: (secs_of_day % 3600 / 60) as u8,
+            sec: (secs_of_day % 60) as u8,
+        })
+    }
+
+    /// Convert a system time into the local calendar time `utc_offset` seconds
+    /// east of UTC
+    pub fn from_system_time_with_offset(time: SystemTime, utc_offset: i32) -> Result<Self> {
+        let offset = Duration::from_secs(u64::from(utc_offset.unsigned_abs()));
+        let local = if utc_offset >= 0 {
+            time.checked_add(offset)
+        } else {
+            time.checked_sub(offset)
+        };
+        Self::from_system_time(local.ok_or_else(|| Error::from("Time is out of range"))?)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Timezone {
+    pub index: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AntiTheftRules {
+    pub rule_list: Vec<AntiTheftRule>,
+    pub enable: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AntiTheftRule {
+    pub id: String,
+    pub name: Option<String>,
+    pub enable: u8,
+    /// Days of the week the rule applies to, starting on Sunday
+    pub wday: Vec<u8>,
+    /// Start time in minutes after midnight
+    pub smin: u16,
+    /// End time in minutes after midnight
+    pub emin: u16,
+    pub repeat: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AddRuleResult {
+    pub id: String,
+}
+
+/// What a relay does when power is restored
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Behavior {
+    /// Return to the state before power was lost
+    LastStatus,
+    /// Always switch on
+    AlwaysOn,
+    /// Always stay off
+    AlwaysOff,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct PowerOnBehaviorResult {
+    pub mode: Behavior,
+}
+
+/// Configuration of a dimmer switch such as the HS220
+///
+/// Times are in milliseconds.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DimmerParameters {
+    /// Lowest brightness the dimmer will go down to
+    pub min_threshold: u16,
+    /// How long switching on fades over
+    pub fade_on_time: u32,
+    /// How long switching off fades over
+    pub fade_off_time: u32,
+    /// How long a gentle switch on, such as from a schedule, fades over
+    pub gentle_on_time: Option<u32>,
+    /// How long a gentle switch off fades over
+    pub gentle_off_time: Option<u32>,
+    /// Brightness change rate when holding the dimmer buttons
+    pub ramp_rate: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeviceIcon {
+    /// Hash identifying the icon
+    pub hash: String,
+    /// Base64 encoded icon image, empty if no icon is set
+    pub icon: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CloudInfo {
+    pub username: Option<String>,
+    pub server: Option<String>,
+    pub binded: u8,
+    pub cld_connection: Option<u8>,
+}
+
+impl CloudInfo {
+    pub fn is_bound(&self) -> bool {
+        self.binded == 1
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.cld_connection == Some(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FirmwareList {
+    pub fw_list: Vec<FirmwareListEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FirmwareListEntry {
+    #[serde(rename = "fwType")]
+    pub fw_type: Option<i32>,
+    #[serde(rename = "fwTitle")]
+    pub title: Option<String>,
+    #[serde(rename = "fwUrl")]
+    pub url: Option<String>,
+    #[serde(rename = "fwReleaseDate")]
+    pub release_date: Option<String>,
+    #[serde(rename = "fwReleaseLog")]
+    pub release_log: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FirmwareInfo {
+    pub sw_ver: String,
+    pub hw_ver: String,
+    pub available: Vec<FirmwareListEntry>,
+}
+
+impl FirmwareInfo {
+    pub fn is_update_available(&self) -> bool {
+        !self.available.is_empty()
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
 
-    pub const HS100_JSON_OFF: &'static str = r#"{
+    pub const HS100_JSON_OFF: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.5.8 Build 180815 Rel.135935",
@@ -322,7 +1454,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const HS100_JSON_ON: &'static str = r#"{
+    pub const HS100_JSON_ON: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.5.8 Build 180815 Rel.135935",
@@ -373,7 +1505,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const HS110_JSON: &'static str = r#"{
+    pub const HS110_JSON: &str = r#"{
       "system": {
         "get_sysinfo": {
           "err_code": 0,
@@ -423,7 +1555,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const HS300_JSON: &'static str = r#"{
+    pub const HS300_JSON: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.0.19 Build 200224 Rel.090814",
@@ -525,7 +1657,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const LB110_JSON_OFF: &'static str = r#"{
+    pub const LB110_JSON_OFF: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.8.6 Build 180809 Rel.091659",
@@ -623,7 +1755,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const LB110_JSON_ON: &'static str = r#"{
+    pub const LB110_JSON_ON: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.8.6 Build 180809 Rel.091659",
@@ -717,7 +1849,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const LB120_JSON: &'static str = r#"{
+    pub const LB120_JSON: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.8.11 Build 191113 Rel.105336",
@@ -811,7 +1943,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const KL110_JSON_OFF: &'static str = r#"{
+    pub const KL110_JSON_OFF: &str = r#"{
       "system": {
         "get_sysinfo": {
           "sw_ver": "1.8.6 Build 180809 Rel.091659",
@@ -909,7 +2041,7 @@ pub mod tests {
       }
     }"#;
 
-    pub const KL110_JSON_ON: &'static str = r#"{
+    pub const KL110_JSON_ON: &str = r#"{
         "system": {
             "get_sysinfo": {
                 "sw_ver": "1.8.11 Build 191113 Rel.105336",
@@ -998,7 +2130,7 @@ pub mod tests {
         }
     }"#;
 
-    pub const KP115_JSON: &'static str = r#"{
+    pub const KP115_JSON: &str = r#"{
       "emeter": {
         "get_realtime": {
           "current_ma": 19,
@@ -1053,38 +2185,239 @@ pub mod tests {
       }
     }"#;
 
+    pub const KL130_JSON_COMMON_SYSTEM: &str = r#"{
+        "smartlife.iot.common.system": {
+            "get_sysinfo": {
+                "sw_ver": "1.8.11 Build 191113 Rel.105336",
+                "hw_ver": "1.0",
+                "model": "KL130(EU)",
+                "description": "Smart Wi-Fi LED Bulb with Dimmable Light",
+                "alias": "Raktár",
+                "mic_type": "IOT.SMARTBULB",
+                "dev_state": "normal",
+                "mic_mac": "50D4F7FB4322",
+                "deviceId": "8012AE9057F11B863567258F4EE165FA1C0E6C93",
+                "oemId": "775B67C11038B99BEEDE39B0C910F6E9",
+                "hwId": "111E35908497A05512E259BB76801E10",
+                "is_factory": false,
+                "disco_ver": "1.0",
+                "ctrl_protocols": {
+                    "name": "Linkie",
+                    "version": "1.0"
+                },
+                "light_state": {
+                    "on_off": 1,
+                    "mode": "normal",
+                    "hue": 0,
+                    "saturation": 0,
+                    "color_temp": 2700,
+                    "brightness": 100
+                },
+                "is_dimmable": 1,
+                "is_color": 0,
+                "is_variable_color_temp": 0,
+                "preferred_state": [{
+                    "index": 0,
+                    "hue": 0,
+                    "saturation": 0,
+                    "color_temp": 2700,
+                    "brightness": 100
+                }, {
+                    "index": 1,
+                    "hue": 0,
+                    "saturation": 0,
+                    "color_temp": 2700,
+                    "brightness": 75
+                }, {
+                    "index": 2,
+                    "hue": 0,
+                    "saturation": 0,
+                    "color_temp": 2700,
+                    "brightness": 25
+                }, {
+                    "index": 3,
+                    "hue": 0,
+                    "saturation": 0,
+                    "color_temp": 2700,
+                    "brightness": 1
+                }],
+                "rssi": -43,
+                "active_mode": "none",
+                "heapsize": 291180,
+                "err_code": 0
+            }
+        },
+        "emeter": {
+            "err_code": -2001,
+            "err_msg": "Module not support"
+        },
+        "smartlife.iot.dimmer": {
+            "err_code": -2001,
+            "err_msg": "Module not support"
+        },
+        "smartlife.iot.common.emeter": {
+            "get_realtime": {
+                "power_mw": 10800,
+                "err_code": 0
+            }
+        },
+        "smartlife.iot.smartbulb.lightingservice": {
+            "get_light_state": {
+                "on_off": 1,
+                "mode": "normal",
+                "hue": 0,
+                "saturation": 0,
+                "color_temp": 2700,
+                "brightness": 100,
+                "err_code": 0
+            }
+        }
+    }"#;
+
+    #[test]
+    fn device_time_from_system_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        assert_eq!(
+            DeviceTime::from_system_time(time).unwrap(),
+            DeviceTime {
+                year: 2020,
+                month: 9,
+                mday: 13,
+                hour: 12,
+                min: 26,
+                sec: 40,
+            }
+        );
+        assert_eq!(
+            DeviceTime::from_system_time(UNIX_EPOCH).unwrap(),
+            DeviceTime {
+                year: 1970,
+                month: 1,
+                mday: 1,
+                hour: 0,
+                min: 0,
+                sec: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn device_time_from_system_time_with_offset() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        assert_eq!(
+            DeviceTime::from_system_time_with_offset(time, 3600).unwrap(),
+            DeviceTime {
+                year: 2020,
+                month: 9,
+                mday: 13,
+                hour: 13,
+                min: 26,
+                sec: 40,
+            }
+        );
+        assert_eq!(
+            DeviceTime::from_system_time_with_offset(time, -13 * 3600).unwrap(),
+            DeviceTime {
+                year: 2020,
+                month: 9,
+                mday: 12,
+                hour: 23,
+                min: 26,
+                sec: 40,
+            }
+        );
+        assert!(DeviceTime::from_system_time_with_offset(UNIX_EPOCH, -60).is_err());
+    }
+
+    #[test]
+    fn set_light_state_builder() {
+        let light_state = SetLightState::builder()
+            .on()
+            .brightness(100)
+            .hue(120)
+            .transition(Duration::from_millis(500))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&light_state).unwrap(),
+            r#"{"on_off":1,"hue":120,"brightness":100,"transition_period":500}"#
+        );
+        assert!(SetLightState::builder().brightness(101).build().is_err());
+        assert!(SetLightState::builder().hue(361).build().is_err());
+        assert!(SetLightState::builder().saturation(101).build().is_err());
+        assert!(SetLightState::builder().color_temp(2000).build().is_err());
+        assert_eq!(
+            LightStateUpdate::new()
+                .color_temp(0)
+                .build()
+                .unwrap()
+                .color_temp,
+            Some(0)
+        );
+        assert!(LightStateUpdate::new().color_temp(9000).build().is_err());
+        assert_eq!(
+            LightStateUpdate::new()
+                .color_temp(9000)
+                .build_with_color_temp_range((2500, 9000))
+                .unwrap()
+                .color_temp,
+            Some(9000)
+        );
+    }
+
     #[test]
     fn deserialise_hs100() {
-        let result = serde_json::from_str::<DeviceData>(&HS100_JSON_OFF).unwrap();
+        let result = serde_json::from_str::<DeviceData>(HS100_JSON_OFF).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "2.1");
         assert_eq!(sysinfo.model, "HS100(UK)");
+        assert_eq!(sysinfo.relay_is_on(), Some(false));
+        assert_eq!(sysinfo.signal_quality(), SignalQuality::Good);
+        assert_eq!(sysinfo.signal_percent(), 94);
+    }
+
+    #[test]
+    fn deserialise_hs100_relay_state_two() {
+        let result = serde_json::from_str::<DeviceData>(HS100_JSON_ON).unwrap();
+
+        assert_eq!(result.sysinfo().relay_is_on(), Some(true));
     }
 
     #[test]
     fn deserialise_hs110() {
-        let result = serde_json::from_str::<DeviceData>(&HS110_JSON).unwrap();
+        let result = serde_json::from_str::<DeviceData>(HS110_JSON).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
         assert_eq!(sysinfo.model, "HS110(UK)");
+        assert_eq!(sysinfo.uptime(), Some(Duration::from_secs(12521)));
+        assert_eq!(sysinfo.signal_quality(), SignalQuality::Excellent);
+        assert_eq!(sysinfo.signal_percent(), 100);
     }
 
     #[test]
     fn deserialise_hs300() {
-        let result = serde_json::from_str::<DeviceData>(&HS300_JSON).unwrap();
+        let result = serde_json::from_str::<DeviceData>(HS300_JSON).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
         assert_eq!(sysinfo.model, "HS300(US)");
         assert_eq!(sysinfo.child_num, Some(6));
         assert_eq!(sysinfo.children.as_ref().map(Vec::len), Some(6));
+        assert_eq!(
+            sysinfo.children.as_ref().unwrap()[0].uptime(),
+            Duration::from_secs(47724)
+        );
+        assert_eq!(sysinfo.uptime(), None);
     }
 
     #[test]
     fn deserialise_lb110_off() {
-        let result = serde_json::from_str::<DeviceData>(&LB110_JSON_OFF).unwrap();
+        let result = serde_json::from_str::<DeviceData>(LB110_JSON_OFF).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
@@ -1111,9 +2444,61 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn deserialise_lb110_ctrl_protocols() {
+        let result = serde_json::from_str::<DeviceData>(LB110_JSON_OFF).unwrap();
+
+        let sysinfo = result.sysinfo();
+        assert_eq!(sysinfo.dev_state.as_deref(), Some("normal"));
+        assert_eq!(sysinfo.disco_ver.as_deref(), Some("1.0"));
+        assert_eq!(
+            sysinfo.ctrl_protocols,
+            Some(CtrlProtocols {
+                name: "Linkie".into(),
+                version: "1.0".into(),
+            })
+        );
+
+        let plug = serde_json::from_str::<DeviceData>(HS100_JSON_OFF).unwrap();
+        assert!(plug.sysinfo().ctrl_protocols.is_none());
+    }
+
+    #[test]
+    fn deserialise_lb110_preferred_states() {
+        let result = serde_json::from_str::<DeviceData>(LB110_JSON_OFF).unwrap();
+
+        let preferred_states = result.sysinfo().preferred_states.as_ref().unwrap();
+        assert_eq!(preferred_states.len(), 4);
+        assert_eq!(preferred_states[1].index, 1);
+        assert_eq!(preferred_states[1].brightness, 80);
+        assert_eq!(preferred_states[1].color_temp, 2700);
+    }
+
+    #[test]
+    fn light_state_current_state() {
+        let on = serde_json::from_str::<LightState>(
+            r#"{"on_off":1,"mode":"normal","hue":0,"saturation":0,"color_temp":2700,"brightness":60,"dft_on_state":{"mode":"normal","hue":0,"saturation":0,"color_temp":2700,"brightness":20}}"#,
+        )
+        .unwrap();
+        let off = serde_json::from_str::<LightState>(
+            r#"{"on_off":0,"dft_on_state":{"mode":"normal","hue":0,"saturation":0,"color_temp":2700,"brightness":20}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(on.current_state().unwrap().brightness, 60);
+        assert_eq!(off.current_state().unwrap().brightness, 20);
+    }
+
+    #[test]
+    fn light_state_without_dft_on_state() {
+        let light_state = serde_json::from_str::<LightState>(r#"{"on_off":1}"#).unwrap();
+
+        assert!(light_state.try_dft_on_state().is_none());
+    }
+
     #[test]
     fn deserialise_lb110_on() {
-        let result = serde_json::from_str::<DeviceData>(&LB110_JSON_ON).unwrap();
+        let result = serde_json::from_str::<DeviceData>(LB110_JSON_ON).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
@@ -1142,7 +2527,7 @@ pub mod tests {
 
     #[test]
     fn deserialise_lb120() {
-        let result = serde_json::from_str::<DeviceData>(&LB120_JSON).unwrap();
+        let result = serde_json::from_str::<DeviceData>(LB120_JSON).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
@@ -1170,7 +2555,7 @@ pub mod tests {
     }
     #[test]
     fn deserialise_kl110_off() {
-        let result = serde_json::from_str::<DeviceData>(&KL110_JSON_OFF).unwrap();
+        let result = serde_json::from_str::<DeviceData>(KL110_JSON_OFF).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
@@ -1197,9 +2582,17 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn deserialise_common_system() {
+        let result = serde_json::from_str::<DeviceData>(KL130_JSON_COMMON_SYSTEM).unwrap();
+
+        assert!(result.sysinfo().model.starts_with("KL130"));
+        assert!(result.sysinfo().light_state.is_some());
+    }
+
     #[test]
     fn deserialise_kl110_on() {
-        let result = serde_json::from_str::<DeviceData>(&KL110_JSON_ON).unwrap();
+        let result = serde_json::from_str::<DeviceData>(KL110_JSON_ON).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
@@ -1228,10 +2621,252 @@ pub mod tests {
 
     #[test]
     fn deserialise_kp115() {
-        let result = serde_json::from_str::<DeviceData>(&KP115_JSON).unwrap();
+        let result = serde_json::from_str::<DeviceData>(KP115_JSON).unwrap();
 
         let sysinfo = result.sysinfo();
         assert_eq!(sysinfo.hw_ver, "1.0");
         assert_eq!(sysinfo.model, "KP115(UK)");
     }
+
+    #[test]
+    fn sysinfo_health() {
+        let plug = serde_json::from_str::<DeviceData>(HS100_JSON_OFF).unwrap();
+        let health = plug.sysinfo().health();
+        assert_eq!(health.free_heap, None);
+        assert!(!health.updating);
+        assert_eq!(health.err_code, 0);
+        assert_eq!(health.ntc_state, Some(0));
+
+        let bulb = serde_json::from_str::<DeviceData>(LB110_JSON_ON).unwrap();
+        let health = bulb.sysinfo().health();
+        assert!(health.free_heap.is_some());
+        assert!(!health.updating);
+        assert_eq!(health.ntc_state, None);
+    }
+
+    #[test]
+    fn sysinfo_color_temp_range() {
+        let bulb = serde_json::from_str::<DeviceData>(LB120_JSON).unwrap();
+        assert_eq!(bulb.sysinfo().color_temp_range(), (2700, 6500));
+        let bulb = serde_json::from_str::<DeviceData>(KL130_JSON_COMMON_SYSTEM).unwrap();
+        assert_eq!(bulb.sysinfo().color_temp_range(), (2500, 9000));
+        let plug = serde_json::from_str::<DeviceData>(HS100_JSON_OFF).unwrap();
+        assert_eq!(plug.sysinfo().color_temp_range(), (2700, 6500));
+    }
+
+    #[test]
+    fn sysinfo_next_action() {
+        let plug = serde_json::from_str::<DeviceData>(HS100_JSON_OFF).unwrap();
+        assert_eq!(plug.sysinfo().next_action(), NextAction::None);
+
+        let scheduled: NextAction =
+            serde_json::from_str(r#"{"type":1,"schd_sec":72000,"action":0}"#).unwrap();
+        assert_eq!(
+            scheduled,
+            NextAction::TurnOff {
+                at: Duration::from_secs(72000)
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&scheduled).unwrap(),
+            r#"{"type":1,"schd_sec":72000,"action":0}"#
+        );
+
+        let untyped = HS100_JSON_OFF.replace(r#""type": -1"#, r#""schd_sec": 3600, "action": 1"#);
+        let plug = serde_json::from_str::<DeviceData>(&untyped).unwrap();
+        assert_eq!(
+            plug.sysinfo().next_action(),
+            NextAction::TurnOn {
+                at: Duration::from_secs(3600)
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<NextAction>("{}").unwrap(),
+            NextAction::None
+        );
+    }
+
+    #[test]
+    fn sysinfo_features() {
+        let plug = serde_json::from_str::<DeviceData>(HS110_JSON).unwrap();
+        assert_eq!(
+            plug.sysinfo().features(),
+            vec![Feature::Timer, Feature::Energy]
+        );
+
+        let mut sysinfo = plug.into_sysinfo();
+        sysinfo.feature = Some(String::from("TIM:XYZ"));
+        assert_eq!(
+            sysinfo.features(),
+            vec![Feature::Timer, Feature::Other(String::from("XYZ"))]
+        );
+        assert!(!sysinfo.has_feature(&Feature::Energy));
+
+        sysinfo.feature = None;
+        assert!(sysinfo.features().is_empty());
+    }
+
+    #[test]
+    fn light_mode() {
+        let state: DftOnState = serde_json::from_str(
+            r#"{"mode":"circadian","hue":0,"saturation":0,"color_temp":2700,"brightness":20}"#,
+        )
+        .unwrap();
+        assert_eq!(state.mode, LightMode::Circadian);
+
+        let modes: Vec<LightMode> =
+            serde_json::from_str(r#"["normal","circadian","party"]"#).unwrap();
+        assert_eq!(
+            modes,
+            vec![
+                LightMode::Normal,
+                LightMode::Circadian,
+                LightMode::Unknown(String::from("party")),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&modes).unwrap(),
+            r#"["normal","circadian","party"]"#
+        );
+    }
+
+    #[test]
+    fn reboot_detector() {
+        let sysinfo = |on_time: i64| {
+            let mut sysinfo = serde_json::from_str::<DeviceData>(HS110_JSON)
+                .unwrap()
+                .into_sysinfo();
+            sysinfo.on_time = Some(on_time);
+            sysinfo
+        };
+        let mut detector = RebootDetector::new();
+
+        assert_eq!(detector.update(&sysinfo(100)), None);
+        assert_eq!(detector.update(&sysinfo(160)), None);
+        assert_eq!(
+            detector.update(&sysinfo(5)),
+            Some(Rebooted {
+                previous: Duration::from_secs(160),
+                current: Duration::from_secs(5),
+            })
+        );
+        assert_eq!(detector.update(&sysinfo(65)), None);
+
+        let mut off = sysinfo(0);
+        off.relay_state = Some(0);
+        assert_eq!(detector.update(&off), None);
+        assert_eq!(detector.update(&sysinfo(10)), None);
+    }
+
+    #[test]
+    fn sysinfo_region() {
+        let mut sysinfo = serde_json::from_str::<DeviceData>(HS110_JSON)
+            .unwrap()
+            .into_sysinfo();
+        assert_eq!(sysinfo.region().as_deref(), Some("UK"));
+
+        sysinfo.model = String::from(" kl130(us) ");
+        assert_eq!(sysinfo.region().as_deref(), Some("US"));
+
+        for model in &["HS100", "HS100()", "HS100(UK"] {
+            sysinfo.model = model.to_string();
+            assert_eq!(sysinfo.region(), None);
+        }
+    }
+
+    #[test]
+    fn sysinfo_is_updating() {
+        let data: DeviceData = serde_json::from_str(HS100_JSON_OFF).unwrap();
+        assert!(!data.sysinfo().is_updating());
+
+        let data: DeviceData =
+            serde_json::from_str(&HS100_JSON_OFF.replace(r#""updating": 0"#, r#""updating": 1"#))
+                .unwrap();
+        assert!(data.sysinfo().is_updating());
+    }
+
+    #[test]
+    fn sysinfo_emeter_type() {
+        let plug = serde_json::from_str::<DeviceData>(HS110_JSON).unwrap();
+        assert_eq!(plug.sysinfo().emeter_type(), Some("emeter"));
+        let bulb = serde_json::from_str::<DeviceData>(LB110_JSON_ON).unwrap();
+        assert_eq!(
+            bulb.sysinfo().emeter_type(),
+            Some("smartlife.iot.common.emeter")
+        );
+    }
+
+    #[test]
+    fn display_summary() {
+        let plug = serde_json::from_str::<DeviceData>(HS110_JSON).unwrap();
+        assert_eq!(
+            plug.to_string(),
+            "Switch One (HS110(UK)) on, signal -40 dBm"
+        );
+        let bulb = serde_json::from_str::<DeviceData>(LB110_JSON_OFF).unwrap();
+        assert!(bulb.sysinfo().to_string().contains(" off, signal "));
+        let strip = serde_json::from_str::<DeviceData>(HS300_JSON).unwrap();
+        assert_eq!(
+            strip.sysinfo().to_string(),
+            "Power Strip (HS300(US)) unknown, signal -61 dBm"
+        );
+    }
+
+    #[test]
+    fn mac_formats() {
+        let plug = serde_json::from_str::<DeviceData>(HS300_JSON).unwrap();
+        assert_eq!(
+            plug.sysinfo().mac_address(),
+            Some([0x68, 0xFF, 0x7B, 0xB8, 0x8C, 0xF6])
+        );
+        assert_eq!(plug.sysinfo().canonical_mac().unwrap(), "68:FF:7B:B8:8C:F6");
+
+        assert_eq!(parse_mac("50d4f7fb4322"), parse_mac("50:D4:F7:FB:43:22"));
+        assert_eq!(
+            parse_mac("50-D4-F7-FB-43-22"),
+            Some([0x50, 0xD4, 0xF7, 0xFB, 0x43, 0x22])
+        );
+        assert_eq!(parse_mac("48:xxxxxx"), None);
+        assert_eq!(parse_mac("50D4F7FB43"), None);
+        assert_eq!(parse_mac("50D4F7FB43é"), None);
+    }
+
+    #[test]
+    fn emeter_realtime_units() {
+        let milli: PlugEmeterRealtime = serde_json::from_str(
+            r#"{"voltage_mv":240000,"current_ma":500,"power_mw":120000,"total_wh":1500,"err_code":0}"#,
+        )
+        .unwrap();
+        assert_eq!(milli.power_mw, Some(120000.0));
+        assert_eq!(milli.power_watts(), 120.0);
+        assert_eq!(milli.voltage_volts(), Some(240.0));
+        assert_eq!(milli.current_amperes(), Some(0.5));
+        assert_eq!(milli.total_kilowatt_hours(), Some(1.5));
+        assert_eq!(
+            serde_json::to_string(&milli).unwrap(),
+            r#"{"current_ma":500.0,"voltage_mv":240000.0,"power_mw":120000.0,"total_wh":1500.0,"err_code":0}"#
+        );
+
+        let base: PlugEmeterRealtime = serde_json::from_str(
+            r#"{"voltage":240.0,"current":0.5,"power":120.0,"total":1.5,"err_code":0}"#,
+        )
+        .unwrap();
+        assert_eq!(base.power_watts(), 120.0);
+        assert_eq!(base.total_kilowatt_hours(), Some(1.5));
+
+        let realtime: EmeterRealtime = serde_json::from_str(
+            r#"{"voltage_mv":240000,"current_ma":500,"power_mw":120000,"total_wh":1500,"err_code":0}"#,
+        )
+        .unwrap();
+        assert_eq!(realtime.power, 120000.0);
+    }
+
+    #[test]
+    fn bulb_emeter_realtime_units() {
+        let data = serde_json::from_str::<DeviceData>(LB110_JSON_ON).unwrap();
+        let realtime = EnergyRealtime::Bulb(data.smartlife.emeter().unwrap().realtime.clone());
+
+        assert_eq!(realtime.power_watts(), 1.8);
+        assert_eq!(realtime.voltage_volts(), None);
+    }
 }
@@ -15,18 +15,32 @@
 //! }
 //! ```
 use std::{
-    net::{AddrParseError, SocketAddr},
-    result,
-    str::FromStr,
+    convert::TryFrom,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::OnceLock,
+    time::Duration,
 };
 
+use rayon::prelude::*;
 use serde::de::DeserializeOwned;
+use serde_json::json;
 
 use crate::{
-    capabilities::{ColorTemperature, DeviceActions, Dimmer, Emeter, Light, MultiSwitch, Switch},
-    datatypes::{DeviceData, GetLightStateResult},
-    error::Result,
-    protocol::{DefaultProtocol, Protocol},
+    capabilities::{
+        detect_emeter_type, find_outlet, AntiTheft, Cloud, ColorTemperature, Colour, DeviceActions,
+        Dimmer, DimmerConfig, Emeter, Firmware, Light, MultiEmeter, MultiSwitch, PowerOnBehavior,
+        Switch, Time,
+    },
+    datatypes::{
+        DeviceData, Feature, GetLightStateResult, SysInfo, SysInfoChild, COMMON_EMETER, EMETER,
+        LB120_COLOR_TEMP_RANGE,
+    },
+    error::{Error, Result},
+    protocol::{
+        decrypt, encrypt, DefaultProtocol, Protocol, DEFAULT_MAX_RESPONSE_SIZE, DEFAULT_PORT,
+    },
 };
 
 // DEVICES
@@ -36,34 +50,350 @@ use crate::{
 pub struct RawDevice<T: Protocol> {
     addr: SocketAddr,
     protocol: T,
+    emeter_type: OnceLock<&'static str>,
+    refuse_while_updating: bool,
 }
 
+/// Methods that change the state of a device, refused during a firmware update
+const STATE_CHANGING_METHODS: &[&str] = &[
+    "set_relay_state",
+    "transition_light_state",
+    "reboot",
+    "reset",
+    "set_mac_addr",
+];
+
 impl RawDevice<DefaultProtocol> {
     /// Make a raw device from an address string
-    pub fn new(addr: &str) -> result::Result<RawDevice<DefaultProtocol>, AddrParseError> {
-        Ok(Self {
-            addr: SocketAddr::from_str(addr)?,
-            protocol: DefaultProtocol::default(),
-        })
+    ///
+    /// See [`resolve_address`](fn.resolve_address.html) for the accepted formats.
+    pub fn new(addr: &str) -> Result<RawDevice<DefaultProtocol>> {
+        Ok(Self::from_addr(resolve_address_with_port(
+            addr,
+            DefaultProtocol::default().default_port(),
+        )?))
     }
 
     /// Make a raw device from an address struct
     pub fn from_addr(addr: SocketAddr) -> Self {
+        Self::with_protocol(addr, DefaultProtocol::default())
+    }
+}
+
+impl<T: Protocol> RawDevice<T> {
+    /// Make a raw device from an address struct using the given transport
+    ///
+    /// ```no_run
+    /// use tplinker::{devices::{RawDevice, HS100}, protocol::UdpProtocol};
+    ///
+    /// let raw = RawDevice::with_protocol("192.168.0.99:9999".parse().unwrap(), UdpProtocol::default());
+    /// let device = HS100::from_raw(raw);
+    /// ```
+    pub fn with_protocol(addr: SocketAddr, protocol: T) -> Self {
         Self {
             addr,
-            protocol: DefaultProtocol::default(),
+            protocol,
+            emeter_type: OnceLock::new(),
+            refuse_while_updating: false,
+        }
+    }
+
+    /// The address commands are sent to
+    pub fn address(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The port commands are sent to
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Send commands to the given port instead, for example a fake device in tests
+    ///
+    /// ```no_run
+    /// use tplinker::devices::RawDevice;
+    ///
+    /// let device = RawDevice::new("127.0.0.1").unwrap().with_port(20002);
+    /// ```
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.addr.set_port(port);
+        self
+    }
+
+    /// Refuse to switch, change the light state, reboot or reset while the device is updating
+    ///
+    /// When enabled the system information is read before each of those commands
+    /// and `Error::Updating` is returned, without sending the command, if the
+    /// device reports it is updating its firmware. This costs an extra request
+    /// per command.
+    ///
+    /// ```no_run
+    /// use tplinker::{capabilities::Switch, devices::{RawDevice, HS100}};
+    ///
+    /// let raw = RawDevice::new("192.168.0.99:9999").unwrap().refuse_while_updating(true);
+    /// HS100::from_raw(raw).switch_on().unwrap();
+    /// ```
+    pub fn refuse_while_updating(mut self, refuse: bool) -> Self {
+        self.refuse_while_updating = refuse;
+        self
+    }
+
+    fn check_not_updating(&self, msg: &str) -> Result<()> {
+        if !self.refuse_while_updating || !is_state_changing(msg) {
+            return Ok(());
+        }
+        if self.sysinfo()?.is_updating() {
+            Err(Error::Updating)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Work out the type of the emeter from the device itself
+    ///
+    /// The type is taken from the hardware type in the system information. If that
+    /// is not recognised both types are tried in turn. The type is kept once it has
+    /// been found so later calls make no requests. Returns `Error::Unsupported` if
+    /// the device has neither.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the device cannot be reached, in which case the next
+    /// call tries again.
+    pub fn detect_emeter_type(&self) -> Result<&'static str> {
+        if let Some(emeter_type) = self.emeter_type.get() {
+            return Ok(emeter_type);
+        }
+        let emeter_type = detect_emeter_type(self)?;
+        Ok(self.emeter_type.get_or_init(|| emeter_type))
+    }
+
+    /// Address a single outlet of a power strip
+    ///
+    /// Every command sent through the returned device carries the outlet's
+    /// `context`, so capabilities such as [`Emeter`](../capabilities/trait.Emeter.html)
+    /// and [`DeviceActions::set_alias`](../capabilities/trait.DeviceActions.html#method.set_alias)
+    /// apply to that outlet alone. The child id is the strip's device id followed by
+    /// the two digit outlet index.
+    ///
+    /// ```no_run
+    /// use tplinker::{capabilities::{Emeter, Switch}, devices::RawDevice};
+    ///
+    /// let strip = RawDevice::new("192.168.0.99:9999").unwrap();
+    /// let outlet = strip.with_child_context("8006D152992421723AD993266C6EC3341B7DF5C602");
+    /// outlet.switch_on().unwrap();
+    /// let realtime = outlet.get_emeter_realtime().unwrap();
+    /// ```
+    pub fn with_child_context(&self, child_id: &str) -> ChildDevice<'_, T> {
+        ChildDevice {
+            raw: self,
+            child_id: child_id.to_string(),
         }
     }
 }
 
 impl<T: Protocol> DeviceActions for RawDevice<T> {
     fn send<'a, D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
-        Ok(serde_json::from_str::<D>(
-            &self.protocol.send(self.addr, msg)?,
-        )?)
+        self.check_not_updating(msg)?;
+        parse_response(&self.protocol.send(self.addr, msg)?)
+    }
+
+    fn send_with_timeout<D: DeserializeOwned>(&self, msg: &str, timeout: Duration) -> Result<D> {
+        self.check_not_updating(msg)?;
+        parse_response(&self.protocol.send_with_timeout(self.addr, msg, timeout)?)
+    }
+}
+
+/// Make model names comparable, for example ` hs110(uk)` becomes `HS110(UK)`
+fn normalise_model(model: &str) -> String {
+    model.trim().to_uppercase()
+}
+
+fn parse_response<D: DeserializeOwned>(response: &str) -> Result<D> {
+    serde_json::from_str::<D>(response).map_err(|err| Error::response_parse(err, response))
+}
+
+/// Resolve an address string to the address of a device
+///
+/// This accepts a socket address such as `192.168.0.99:9999`, an IP address
+/// such as `192.168.0.99`, or a host name with or without a port such as
+/// `plug.local`. The port defaults to [`DEFAULT_PORT`](../protocol/constant.DEFAULT_PORT.html).
+///
+/// ```
+/// use tplinker::devices::resolve_address;
+///
+/// assert_eq!(resolve_address("192.168.0.99").unwrap(), "192.168.0.99:9999".parse().unwrap());
+/// ```
+///
+/// # Errors
+///
+/// Will return `Err` if a host name cannot be resolved.
+pub fn resolve_address(addr: &str) -> Result<SocketAddr> {
+    resolve_address_with_port(addr, DEFAULT_PORT)
+}
+
+/// Resolve an address string like [`resolve_address`](fn.resolve_address.html),
+/// using `default_port` if the string has no port
+///
+/// # Errors
+///
+/// Will return `Err` if a host name cannot be resolved.
+pub fn resolve_address_with_port(addr: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+    let resolved = match addr.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>().map_err(|_| invalid_address(addr))?,
+        )
+            .to_socket_addrs()?
+            .next(),
+        None => (addr, default_port).to_socket_addrs()?.next(),
+    };
+    resolved.ok_or_else(|| invalid_address(addr))
+}
+
+fn invalid_address(addr: &str) -> Error {
+    Error::from(format!("not a valid address: {}", addr))
+}
+
+fn is_state_changing(msg: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(msg)
+        .ok()
+        .as_ref()
+        .and_then(serde_json::Value::as_object)
+        .is_some_and(|sections| {
+            sections
+                .values()
+                .filter_map(serde_json::Value::as_object)
+                .any(|methods| {
+                    STATE_CHANGING_METHODS
+                        .iter()
+                        .any(|method| methods.contains_key(*method))
+                })
+        })
+}
+
+// Send commands through a field of a wrapper, optionally passing each command
+// through one of its methods first
+macro_rules! forward_device_actions {
+    ( $x:ident<$lt:lifetime> => $field:ident ) => {
+        impl<$lt> DeviceActions for $x<$lt> {
+            fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
+                self.$field.send(msg)
+            }
+
+            fn send_with_timeout<D: DeserializeOwned>(
+                &self,
+                msg: &str,
+                timeout: Duration,
+            ) -> Result<D> {
+                self.$field.send_with_timeout(msg, timeout)
+            }
+        }
+    };
+    ( $x:ident<$lt:lifetime, T> => $field:ident ) => {
+        impl<$lt, T: Protocol> DeviceActions for $x<$lt, T> {
+            fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
+                self.$field.send(msg)
+            }
+
+            fn send_with_timeout<D: DeserializeOwned>(
+                &self,
+                msg: &str,
+                timeout: Duration,
+            ) -> Result<D> {
+                self.$field.send_with_timeout(msg, timeout)
+            }
+        }
+    };
+    ( $x:ident<$lt:lifetime, T> => $field:ident, $map:ident ) => {
+        impl<$lt, T: Protocol> DeviceActions for $x<$lt, T> {
+            fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
+                self.$field.send(&self.$map(msg)?)
+            }
+
+            fn send_with_timeout<D: DeserializeOwned>(
+                &self,
+                msg: &str,
+                timeout: Duration,
+            ) -> Result<D> {
+                self.$field.send_with_timeout(&self.$map(msg)?, timeout)
+            }
+        }
+    };
+}
+
+/// A raw device known to have a relay, because its system information reports a relay state
+///
+/// This lets `Unknown` devices be switched, see [`Device::try_switch`](enum.Device.html#method.try_switch).
+struct RelayDevice<'a, T: Protocol> {
+    raw: &'a RawDevice<T>,
+}
+
+forward_device_actions!(RelayDevice<'a, T> => raw);
+
+impl<'a, T: Protocol> Switch for RelayDevice<'a, T> {}
+
+/// A single outlet of a power strip, see [`RawDevice::with_child_context`](struct.RawDevice.html#method.with_child_context)
+#[derive(Clone, Debug)]
+pub struct ChildDevice<'a, T: Protocol> {
+    raw: &'a RawDevice<T>,
+    child_id: String,
+}
+
+impl<'a, T: Protocol> ChildDevice<'a, T> {
+    /// The id of the outlet commands are sent to
+    pub fn child_id(&self) -> &str {
+        &self.child_id
+    }
+}
+
+impl<'a, T: Protocol> ChildDevice<'a, T> {
+    /// Add the outlet's context to a command
+    fn with_context(&self, msg: &str) -> Result<String> {
+        let mut command = serde_json::from_str::<serde_json::Value>(msg)?;
+        let sections = command
+            .as_object_mut()
+            .ok_or_else(|| Error::from("Command is not a JSON object"))?;
+        sections.insert(
+            String::from("context"),
+            json!({"child_ids": [self.child_id]}),
+        );
+        Ok(command.to_string())
+    }
+}
+
+forward_device_actions!(ChildDevice<'a, T> => raw, with_context);
+
+impl<'a, T: Protocol> Switch for ChildDevice<'a, T> {
+    /// Check whether the outlet is on
+    ///
+    /// The system information of a strip is the same for every outlet so this
+    /// looks the outlet up in its children.
+    fn is_on(&self) -> Result<bool> {
+        let sysinfo = self.sysinfo()?;
+        sysinfo
+            .children
+            .as_ref()
+            .and_then(|children| {
+                children.iter().find(|child| {
+                    child.id == self.child_id
+                        || format!("{}{}", sysinfo.device_id, child.id) == self.child_id
+                })
+            })
+            .map(|child| child.is_on())
+            .ok_or_else(|| Error::from("Invalid outlet id"))
     }
 }
 
+impl<'a, T: Protocol> Emeter for ChildDevice<'a, T> {}
+impl<'a, T: Protocol> Time for ChildDevice<'a, T> {}
+
 macro_rules! new_device {
     ( $x:ident, $description:expr ) => {
         new_device! {
@@ -87,7 +417,7 @@ macro_rules! new_device {
 
         impl $x<DefaultProtocol> {
             #[doc = $docnew]
-            pub fn new(addr: &str) -> std::result::Result<Self, AddrParseError> {
+            pub fn new(addr: &str) -> Result<Self> {
                 Ok(Self {
                     raw: RawDevice::new(addr)?,
                 })
@@ -112,6 +442,33 @@ macro_rules! new_device {
             fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
                 self.raw.send(msg)
             }
+
+            fn send_with_timeout<D: DeserializeOwned>(
+                &self,
+                msg: &str,
+                timeout: Duration,
+            ) -> Result<D> {
+                self.raw.send_with_timeout(msg, timeout)
+            }
+        }
+
+        /// Fails if the model in the device data is not a
+        #[doc = stringify!($x)]
+        impl TryFrom<(SocketAddr, &DeviceData)> for $x<DefaultProtocol> {
+            type Error = Error;
+
+            fn try_from((addr, device_data): (SocketAddr, &DeviceData)) -> Result<Self> {
+                let model = &device_data.sysinfo().model;
+                if normalise_model(model).contains(stringify!($x)) {
+                    Ok(Self::from_addr(addr))
+                } else {
+                    Err(Error::Other(format!(
+                        "{} is not a {}",
+                        model,
+                        stringify!($x)
+                    )))
+                }
+            }
         }
     };
 }
@@ -119,44 +476,92 @@ macro_rules! new_device {
 new_device!(HS100, "smart plug");
 
 impl<T: Protocol> Switch for HS100<T> {}
+impl<T: Protocol> AntiTheft for HS100<T> {}
+impl<T: Protocol> Cloud for HS100<T> {}
+impl<T: Protocol> PowerOnBehavior for HS100<T> {}
+impl<T: Protocol> Firmware for HS100<T> {}
+impl<T: Protocol> Time for HS100<T> {}
 
 new_device!(HS103, "smart plug");
 
 impl<T: Protocol> Switch for HS103<T> {}
+impl<T: Protocol> AntiTheft for HS103<T> {}
+impl<T: Protocol> Cloud for HS103<T> {}
+impl<T: Protocol> PowerOnBehavior for HS103<T> {}
+impl<T: Protocol> Firmware for HS103<T> {}
+impl<T: Protocol> Time for HS103<T> {}
 
 new_device!(HS105, "smart plug mini");
 
 impl<T: Protocol> Switch for HS105<T> {}
+impl<T: Protocol> AntiTheft for HS105<T> {}
+impl<T: Protocol> Cloud for HS105<T> {}
+impl<T: Protocol> PowerOnBehavior for HS105<T> {}
+impl<T: Protocol> Firmware for HS105<T> {}
+impl<T: Protocol> Time for HS105<T> {}
 
 new_device!(HS110, "smart plug with energy monitoring");
 
 impl<T: Protocol> Switch for HS110<T> {}
 impl<T: Protocol> Emeter for HS110<T> {}
+impl<T: Protocol> AntiTheft for HS110<T> {}
+impl<T: Protocol> Cloud for HS110<T> {}
+impl<T: Protocol> PowerOnBehavior for HS110<T> {}
+impl<T: Protocol> Firmware for HS110<T> {}
+impl<T: Protocol> Time for HS110<T> {}
 
 new_device!(KP115, "smart plug mini with energy monitoring");
 
 impl<T: Protocol> Switch for KP115<T> {}
 impl<T: Protocol> Emeter for KP115<T> {}
+impl<T: Protocol> AntiTheft for KP115<T> {}
+impl<T: Protocol> Cloud for KP115<T> {}
+impl<T: Protocol> PowerOnBehavior for KP115<T> {}
+impl<T: Protocol> Firmware for KP115<T> {}
+impl<T: Protocol> Time for KP115<T> {}
+
+new_device!(KP105, "smart plug mini");
+
+impl<T: Protocol> Switch for KP105<T> {}
+impl<T: Protocol> AntiTheft for KP105<T> {}
+impl<T: Protocol> Cloud for KP105<T> {}
+impl<T: Protocol> PowerOnBehavior for KP105<T> {}
+impl<T: Protocol> Firmware for KP105<T> {}
+impl<T: Protocol> Time for KP105<T> {}
+
+new_device!(KP125, "smart plug mini with energy monitoring");
+
+impl<T: Protocol> Switch for KP125<T> {}
+impl<T: Protocol> Emeter for KP125<T> {}
+impl<T: Protocol> AntiTheft for KP125<T> {}
+impl<T: Protocol> Cloud for KP125<T> {}
+impl<T: Protocol> PowerOnBehavior for KP125<T> {}
+impl<T: Protocol> Firmware for KP125<T> {}
+impl<T: Protocol> Time for KP125<T> {}
 
 new_device!(HS300, "smart power strip with energy monitoring");
 
 impl<T: Protocol> MultiSwitch for HS300<T> {}
 impl<T: Protocol> Emeter for HS300<T> {}
+impl<T: Protocol> MultiEmeter for HS300<T> {}
+impl<T: Protocol> Cloud for HS300<T> {}
+impl<T: Protocol> Firmware for HS300<T> {}
+impl<T: Protocol> Time for HS300<T> {}
 
 new_device!(LB110, "dimmable smart lightbulb");
 
 impl<T: Protocol> Switch for LB110<T> {
     fn is_on(&self) -> Result<bool> {
-        Ok(self.get_light_state()?.on_off == 1)
+        self.light_is_on()
     }
 
     fn switch_on(&self) -> Result<()> {
-        self.send(&r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#)?;
+        self.send::<GetLightStateResult>(r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#)?;
         Ok(())
     }
 
     fn switch_off(&self) -> Result<()> {
-        self.send(&r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":0}}}"#)?;
+        self.send::<GetLightStateResult>(r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":0}}}"#)?;
         Ok(())
     }
 }
@@ -167,25 +572,40 @@ impl<T: Protocol> Emeter for LB110<T> {
         String::from("smartlife.iot.common.emeter")
     }
 }
+impl<T: Protocol> Cloud for LB110<T> {
+    fn cloud_type(&self) -> String {
+        String::from("smartlife.iot.common.cloud")
+    }
+}
+impl<T: Protocol> Firmware for LB110<T> {}
+impl<T: Protocol> Time for LB110<T> {
+    fn time_type(&self) -> String {
+        String::from("smartlife.iot.common.timesetting")
+    }
+}
 
 new_device!(LB120, "tunable white color smart lightbulb");
 
 impl<T: Protocol> Switch for LB120<T> {
     fn is_on(&self) -> Result<bool> {
-        Ok(self.get_light_state()?.on_off == 1)
+        self.light_is_on()
     }
 
     fn switch_on(&self) -> Result<()> {
-        self.send(&r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#)?;
+        self.send::<GetLightStateResult>(r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#)?;
         Ok(())
     }
 
     fn switch_off(&self) -> Result<()> {
-        self.send(&r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":0}}}"#)?;
+        self.send::<GetLightStateResult>(r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":0}}}"#)?;
         Ok(())
     }
 }
-impl<T: Protocol> Light for LB120<T> {}
+impl<T: Protocol> Light for LB120<T> {
+    fn color_temp_range(&self) -> (u16, u16) {
+        LB120_COLOR_TEMP_RANGE
+    }
+}
 impl<T: Protocol> Dimmer for LB120<T> {}
 impl<T: Protocol> ColorTemperature for LB120<T> {}
 impl<T: Protocol> Emeter for LB120<T> {
@@ -193,21 +613,32 @@ impl<T: Protocol> Emeter for LB120<T> {
         String::from("smartlife.iot.common.emeter")
     }
 }
+impl<T: Protocol> Cloud for LB120<T> {
+    fn cloud_type(&self) -> String {
+        String::from("smartlife.iot.common.cloud")
+    }
+}
+impl<T: Protocol> Firmware for LB120<T> {}
+impl<T: Protocol> Time for LB120<T> {
+    fn time_type(&self) -> String {
+        String::from("smartlife.iot.common.timesetting")
+    }
+}
 
 new_device!(KL110, "dimmable smart lightbulb");
 
 impl<T: Protocol> Switch for KL110<T> {
     fn is_on(&self) -> Result<bool> {
-        Ok(self.get_light_state()?.on_off == 1)
+        self.light_is_on()
     }
 
     fn switch_on(&self) -> Result<()> {
-        self.send::<GetLightStateResult>(&r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#)?;
+        self.send::<GetLightStateResult>(r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1}}}"#)?;
         Ok(())
     }
 
     fn switch_off(&self) -> Result<()> {
-        self.send::<GetLightStateResult>(&r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":0}}}"#)?;
+        self.send::<GetLightStateResult>(r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":0}}}"#)?;
         Ok(())
     }
 }
@@ -218,12 +649,102 @@ impl<T: Protocol> Emeter for KL110<T> {
         String::from("smartlife.iot.common.emeter")
     }
 }
+impl<T: Protocol> Cloud for KL110<T> {
+    fn cloud_type(&self) -> String {
+        String::from("smartlife.iot.common.cloud")
+    }
+}
+impl<T: Protocol> Firmware for KL110<T> {}
+impl<T: Protocol> Time for KL110<T> {
+    fn time_type(&self) -> String {
+        String::from("smartlife.iot.common.timesetting")
+    }
+}
+
+macro_rules! with_switch {
+    ( $device:expr, $d:ident => $body:expr ) => {
+        match $device {
+            Device::HS100($d) => $body,
+            Device::HS103($d) => $body,
+            Device::HS105($d) => $body,
+            Device::HS110($d) => $body,
+            Device::KP115($d) => $body,
+            Device::KP105($d) => $body,
+            Device::KP125($d) => $body,
+            Device::LB110($d) => $body,
+            Device::LB120($d) => $body,
+            Device::KL110($d) => $body,
+            Device::Unknown(raw) => {
+                if raw.sysinfo()?.relay_state.is_some() {
+                    let $d = &RelayDevice { raw };
+                    $body
+                } else {
+                    Err(Error::from("Device is not switchable"))
+                }
+            }
+            _ => Err(Error::from("Device is not switchable")),
+        }
+    };
+}
+
+macro_rules! with_dimmer {
+    ( $device:expr, $d:ident => $body:expr ) => {
+        match $device {
+            Device::LB110($d) => $body,
+            Device::LB120($d) => $body,
+            Device::KL110($d) => $body,
+            _ => Err(Error::from("Device is not dimmable")),
+        }
+    };
+}
+
+/// A summary of what a device is capable of
+///
+/// Each flag corresponds to one or more traits in
+/// [`capabilities`](../capabilities/index.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceCapabilities {
+    /// Can be switched on and off, see [`Switch`](../capabilities/trait.Switch.html)
+    pub switchable: bool,
+    /// Brightness can be changed, see [`Dimmer`](../capabilities/trait.Dimmer.html)
+    pub dimmable: bool,
+    /// Colour can be changed, see [`Colour`](../capabilities/trait.Colour.html)
+    pub color: bool,
+    /// Color temperature can be changed, see
+    /// [`ColorTemperature`](../capabilities/trait.ColorTemperature.html)
+    pub variable_color_temp: bool,
+    /// Energy usage is tracked, see [`Emeter`](../capabilities/trait.Emeter.html)
+    pub emeter: bool,
+    /// Has multiple outlets, see [`MultiSwitch`](../capabilities/trait.MultiSwitch.html)
+    pub multi_outlet: bool,
+}
+
+impl DeviceCapabilities {
+    /// Work out capabilities from the flags reported in system information
+    ///
+    /// This is useful for devices which are not recognised.
+    pub fn from_sysinfo(sysinfo: &SysInfo) -> Self {
+        let is_light = sysinfo.light_state.is_some();
+        Self {
+            switchable: sysinfo.relay_state.is_some() || is_light,
+            dimmable: sysinfo.is_dimmable(),
+            color: sysinfo.is_color(),
+            variable_color_temp: sysinfo.is_variable_color_temp(),
+            emeter: is_light || sysinfo.has_feature(&Feature::Energy),
+            multi_outlet: sysinfo.children.is_some(),
+        }
+    }
+}
 
 /// An enum of the available device types.
 ///
 /// This is returned from [`discover`](../discovery/fn.discover.html).
 /// If the device type is not recognised but we can parse the response the
 /// `Unknown` variant is returned.
+///
+/// Devices are equal, and hash the same, when they have the same
+/// [`address`](#method.address), whatever their model or state. This makes them
+/// usable as keys in a `HashMap` or `HashSet`.
 #[derive(Clone, Debug)]
 pub enum Device {
     /// Device variant for an HS100 smart plug
@@ -244,15 +765,286 @@ pub enum Device {
     KL110(KL110<DefaultProtocol>),
     /// Device variant for an KP115 smart plug
     KP115(KP115<DefaultProtocol>),
+    /// Device variant for an KP105 smart plug
+    KP105(KP105<DefaultProtocol>),
+    /// Device variant for an KP125 smart plug
+    KP125(KP125<DefaultProtocol>),
     /// Device variant for an unknown device
     Unknown(RawDevice<DefaultProtocol>),
 }
 
 impl Device {
-    /// Create a device from a raw `DeviceData`. Usually used from
-    /// [`discover`](../discovery/fn.discover.html).
-    pub fn from_data(addr: SocketAddr, device_data: &DeviceData) -> Device {
-        let model = &device_data.sysinfo().model;
+    /// Get the capabilities of the device based on its type
+    ///
+    /// The `Unknown` variant has no capabilities, use
+    /// [`DeviceCapabilities::from_sysinfo`](struct.DeviceCapabilities.html#method.from_sysinfo)
+    /// to work them out from its system information instead.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let switch = DeviceCapabilities {
+            switchable: true,
+            ..DeviceCapabilities::default()
+        };
+        let dimmable_light = DeviceCapabilities {
+            dimmable: true,
+            emeter: true,
+            ..switch
+        };
+        match self {
+            Device::HS100(_) | Device::HS103(_) | Device::HS105(_) | Device::KP105(_) => switch,
+            Device::HS110(_) | Device::KP115(_) | Device::KP125(_) => DeviceCapabilities {
+                emeter: true,
+                ..switch
+            },
+            Device::HS300(_) => DeviceCapabilities {
+                emeter: true,
+                multi_outlet: true,
+                ..DeviceCapabilities::default()
+            },
+            Device::LB110(_) | Device::KL110(_) => dimmable_light,
+            Device::LB120(_) => DeviceCapabilities {
+                variable_color_temp: true,
+                ..dimmable_light
+            },
+            Device::Unknown(_) => DeviceCapabilities::default(),
+        }
+    }
+
+    /// Whether the device has an energy meter, without making a request
+    ///
+    /// This is based on the device type so is `false` for `Unknown` devices. For
+    /// those check the `ENE` feature in their system information with
+    /// [`DeviceCapabilities::from_sysinfo`](struct.DeviceCapabilities.html#method.from_sysinfo),
+    /// or use [`try_emeter`](#method.try_emeter).
+    pub fn has_emeter(&self) -> bool {
+        self.capabilities().emeter
+    }
+
+    /// Get a handle implementing [`Emeter`](../capabilities/trait.Emeter.html)
+    /// if the device has an energy meter
+    ///
+    /// This makes no request for device types with a known energy meter, see
+    /// [`has_emeter`](#method.has_emeter). The energy meter of `Unknown` devices
+    /// is detected with [`RawDevice::detect_emeter_type`](struct.RawDevice.html#method.detect_emeter_type).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the energy meter of an `Unknown` device cannot be
+    /// detected because the device cannot be reached.
+    pub fn try_emeter(&self) -> Result<Option<EmeterDevice<'_>>> {
+        let emeter_type = match self {
+            Device::LB110(_) | Device::LB120(_) | Device::KL110(_) => Some(COMMON_EMETER),
+            Device::Unknown(d) => match d.detect_emeter_type() {
+                Ok(emeter_type) => Some(emeter_type),
+                Err(err) if err.is_unsupported() => None,
+                Err(err) => return Err(err),
+            },
+            device if device.has_emeter() => Some(EMETER),
+            _ => None,
+        };
+        Ok(emeter_type.map(|emeter_type| EmeterDevice {
+            device: self,
+            emeter_type,
+        }))
+    }
+
+    /// Get the system information of the specified outlet of a power strip
+    ///
+    /// [`sysinfo`](../capabilities/trait.DeviceActions.html#tymethod.sysinfo)
+    /// gives the information for the whole strip. The index is the same as for
+    /// [`MultiSwitch`](../capabilities/trait.MultiSwitch.html). Returns `Error::Unsupported`
+    /// if the device does not have [`multiple outlets`](struct.DeviceCapabilities.html#structfield.multi_outlet).
+    pub fn child_sysinfo(&self, index: usize) -> Result<SysInfoChild> {
+        match self {
+            Device::HS300(d) => find_outlet(&d.sysinfo()?, index).cloned(),
+            _ => Err(Error::Unsupported(String::from(
+                "Device does not have multiple outlets",
+            ))),
+        }
+    }
+
+    /// Get a handle implementing [`Switch`](../capabilities/trait.Switch.html)
+    /// if the device is switchable
+    ///
+    /// `Unknown` devices are switchable when their system information includes
+    /// a relay state, which requires a request to the device.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the system information of an `Unknown` device
+    /// cannot be read.
+    pub fn try_switch(&self) -> Result<Option<SwitchableDevice<'_>>> {
+        let switchable = match self {
+            Device::HS300(_) => false,
+            Device::Unknown(d) => d.sysinfo()?.relay_state.is_some(),
+            _ => true,
+        };
+        Ok(if switchable {
+            Some(SwitchableDevice { device: self })
+        } else {
+            None
+        })
+    }
+
+    /// Get a handle implementing [`Colour`](../capabilities/trait.Colour.html)
+    /// if the device supports colour
+    ///
+    /// Colour support is read from the `is_color` flag in the system information
+    /// of bulbs and `Unknown` devices, which requires a request to the device, so
+    /// that colour bulbs without their own device type are included.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the system information cannot be read.
+    pub fn try_colour(&self) -> Result<Option<ColourDevice<'_>>> {
+        let sysinfo = match self {
+            Device::LB110(_) | Device::LB120(_) | Device::KL110(_) | Device::Unknown(_) => {
+                self.sysinfo()?
+            }
+            _ => return Ok(None),
+        };
+        Ok(if sysinfo.is_color() {
+            Some(ColourDevice {
+                device: self,
+                color_temp_range: sysinfo.color_temp_range(),
+            })
+        } else {
+            None
+        })
+    }
+
+    /// Get a handle implementing [`ColorTemperature`](../capabilities/trait.ColorTemperature.html)
+    /// if the device supports a variable color temperature
+    ///
+    /// Support is read from the `is_variable_color_temp` flag in the system
+    /// information of bulbs and `Unknown` devices, which requires a request to the
+    /// device. The handle does not check again on each change, and uses the
+    /// [`color_temp_range`](../datatypes/struct.SysInfo.html#method.color_temp_range)
+    /// of the model.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the system information cannot be read.
+    pub fn try_color_temperature(&self) -> Result<Option<ColorTemperatureDevice<'_>>> {
+        let sysinfo = match self {
+            Device::LB110(_) | Device::LB120(_) | Device::KL110(_) | Device::Unknown(_) => {
+                self.sysinfo()?
+            }
+            _ => return Ok(None),
+        };
+        Ok(if sysinfo.is_variable_color_temp() {
+            Some(ColorTemperatureDevice {
+                device: self,
+                color_temp_range: sysinfo.color_temp_range(),
+            })
+        } else {
+            None
+        })
+    }
+
+    /// Get a handle implementing [`DimmerConfig`](../capabilities/trait.DimmerConfig.html)
+    /// if the device is a dimmer switch such as the HS220
+    ///
+    /// Dimmer switches do not have their own device type yet so are `Unknown`
+    /// devices. They are recognised by reporting both a relay state and a
+    /// brightness in their system information, which requires a request to the device.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the system information of an `Unknown` device
+    /// cannot be read.
+    pub fn try_dimmer_config(&self) -> Result<Option<DimmerConfigDevice<'_>>> {
+        let dimmer = match self {
+            Device::Unknown(d) => {
+                let sysinfo = d.sysinfo()?;
+                sysinfo.relay_state.is_some() && sysinfo.brightness.is_some()
+            }
+            _ => false,
+        };
+        Ok(if dimmer {
+            Some(DimmerConfigDevice { device: self })
+        } else {
+            None
+        })
+    }
+
+    /// Check whether the device is on
+    ///
+    /// Returns an error if the device is not [`switchable`](struct.DeviceCapabilities.html#structfield.switchable).
+    /// `Unknown` devices are switchable if they report a relay state.
+    pub fn is_on(&self) -> Result<bool> {
+        with_switch!(self, d => d.is_on())
+    }
+
+    /// Switch the device on
+    ///
+    /// Returns an error if the device is not [`switchable`](struct.DeviceCapabilities.html#structfield.switchable).
+    pub fn switch_on(&self) -> Result<()> {
+        with_switch!(self, d => d.switch_on())
+    }
+
+    /// Switch the device off
+    ///
+    /// Returns an error if the device is not [`switchable`](struct.DeviceCapabilities.html#structfield.switchable).
+    pub fn switch_off(&self) -> Result<()> {
+        with_switch!(self, d => d.switch_off())
+    }
+
+    /// Toggle the device's on state, returning whether it is now on
+    ///
+    /// Returns an error if the device is not [`switchable`](struct.DeviceCapabilities.html#structfield.switchable).
+    pub fn toggle(&self) -> Result<bool> {
+        with_switch!(self, d => d.toggle())
+    }
+
+    /// Toggle the device's on state given whether it is currently on, returning
+    /// whether it is now on
+    ///
+    /// See [`Switch::toggle_from`](../capabilities/trait.Switch.html#method.toggle_from).
+    /// Returns an error if the device is not [`switchable`](struct.DeviceCapabilities.html#structfield.switchable).
+    pub fn toggle_from(&self, known_state: bool) -> Result<bool> {
+        with_switch!(self, d => d.toggle_from(known_state))
+    }
+
+    /// Get percentage brightness of bulb
+    ///
+    /// Returns an error if the device is not [`dimmable`](struct.DeviceCapabilities.html#structfield.dimmable).
+    pub fn brightness(&self) -> Result<u16> {
+        with_dimmer!(self, d => d.brightness())
+    }
+
+    /// Set percentage brightness of bulb
+    ///
+    /// Returns an error if the device is not [`dimmable`](struct.DeviceCapabilities.html#structfield.dimmable).
+    pub fn set_brightness(&self, brightness: u16) -> Result<()> {
+        with_dimmer!(self, d => d.set_brightness(brightness))
+    }
+
+    /// The address commands are sent to
+    pub fn address(&self) -> SocketAddr {
+        match self {
+            Device::HS100(d) => d.raw.addr,
+            Device::HS103(d) => d.raw.addr,
+            Device::HS105(d) => d.raw.addr,
+            Device::HS110(d) => d.raw.addr,
+            Device::HS300(d) => d.raw.addr,
+            Device::LB110(d) => d.raw.addr,
+            Device::LB120(d) => d.raw.addr,
+            Device::KL110(d) => d.raw.addr,
+            Device::KP115(d) => d.raw.addr,
+            Device::KP105(d) => d.raw.addr,
+            Device::KP125(d) => d.raw.addr,
+            Device::Unknown(d) => d.address(),
+        }
+    }
+
+    /// Create a device from a model string such as `HS110(UK)`, for example
+    /// one read from a config file
+    ///
+    /// Case and surrounding whitespace are ignored, as is the region. Models
+    /// that are not recognised give `Device::Unknown`. No request is made to
+    /// check the device at `addr` is the given model.
+    pub fn for_model(model: &str, addr: SocketAddr) -> Device {
+        let model = normalise_model(model);
         if model.contains("HS100") {
             Device::HS100(HS100::from_addr(addr))
         } else if model.contains("HS103") {
@@ -271,12 +1063,132 @@ impl Device {
             Device::KL110(KL110::from_addr(addr))
         } else if model.contains("KP115") {
             Device::KP115(KP115::from_addr(addr))
+        } else if model.contains("KP105") {
+            Device::KP105(KP105::from_addr(addr))
+        } else if model.contains("KP125") {
+            Device::KP125(KP125::from_addr(addr))
         } else {
             Device::Unknown(RawDevice::from_addr(addr))
         }
     }
+
+    /// Identify the device at `addr` by reading its system information
+    ///
+    /// The model in the system information is used to pick the device variant,
+    /// see [`for_model`](#method.for_model).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the system information cannot be read.
+    pub fn identify(addr: SocketAddr) -> Result<(Device, SysInfo)> {
+        let sysinfo = RawDevice::from_addr(addr).sysinfo()?;
+        Ok((Device::for_model(&sysinfo.model, addr), sysinfo))
+    }
+
+    /// Create a device from a raw `DeviceData`. Usually used from
+    /// [`discover`](../discovery/fn.discover.html).
+    pub fn from_data(addr: SocketAddr, device_data: &DeviceData) -> Device {
+        Device::for_model(&device_data.sysinfo().model, addr)
+    }
+}
+
+impl PartialEq for Device {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Eq for Device {}
+
+impl Hash for Device {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address().hash(state);
+    }
+}
+
+/// A switchable [`Device`](enum.Device.html), see [`Device::try_switch`](enum.Device.html#method.try_switch)
+#[derive(Clone, Copy, Debug)]
+pub struct SwitchableDevice<'a> {
+    device: &'a Device,
+}
+
+forward_device_actions!(SwitchableDevice<'a> => device);
+
+impl<'a> Switch for SwitchableDevice<'a> {
+    fn is_on(&self) -> Result<bool> {
+        self.device.is_on()
+    }
+
+    fn switch_on(&self) -> Result<()> {
+        self.device.switch_on()
+    }
+
+    fn switch_off(&self) -> Result<()> {
+        self.device.switch_off()
+    }
 }
 
+/// A [`Device`](enum.Device.html) that supports colour, see [`Device::try_colour`](enum.Device.html#method.try_colour)
+#[derive(Clone, Copy, Debug)]
+pub struct ColourDevice<'a> {
+    device: &'a Device,
+    color_temp_range: (u16, u16),
+}
+
+forward_device_actions!(ColourDevice<'a> => device);
+
+impl<'a> Light for ColourDevice<'a> {
+    fn color_temp_range(&self) -> (u16, u16) {
+        self.color_temp_range
+    }
+}
+impl<'a> Dimmer for ColourDevice<'a> {}
+impl<'a> Colour for ColourDevice<'a> {}
+
+/// A [`Device`](enum.Device.html) with a variable color temperature, see
+/// [`Device::try_color_temperature`](enum.Device.html#method.try_color_temperature)
+#[derive(Clone, Copy, Debug)]
+pub struct ColorTemperatureDevice<'a> {
+    device: &'a Device,
+    color_temp_range: (u16, u16),
+}
+
+forward_device_actions!(ColorTemperatureDevice<'a> => device);
+
+impl<'a> Light for ColorTemperatureDevice<'a> {
+    fn color_temp_range(&self) -> (u16, u16) {
+        self.color_temp_range
+    }
+}
+impl<'a> Dimmer for ColorTemperatureDevice<'a> {}
+impl<'a> ColorTemperature for ColorTemperatureDevice<'a> {}
+
+/// A [`Device`](enum.Device.html) with an energy meter, see [`Device::try_emeter`](enum.Device.html#method.try_emeter)
+#[derive(Clone, Copy, Debug)]
+pub struct EmeterDevice<'a> {
+    device: &'a Device,
+    emeter_type: &'static str,
+}
+
+forward_device_actions!(EmeterDevice<'a> => device);
+
+impl<'a> Emeter for EmeterDevice<'a> {
+    fn emeter_type(&self) -> String {
+        String::from(self.emeter_type)
+    }
+}
+
+/// A [`Device`](enum.Device.html) that is a dimmer switch, see
+/// [`Device::try_dimmer_config`](enum.Device.html#method.try_dimmer_config)
+#[derive(Clone, Copy, Debug)]
+pub struct DimmerConfigDevice<'a> {
+    device: &'a Device,
+}
+
+forward_device_actions!(DimmerConfigDevice<'a> => device);
+
+impl<'a> DimmerConfig for DimmerConfigDevice<'a> {}
+
 impl DeviceActions for Device {
     fn send<D: DeserializeOwned>(&self, msg: &str) -> Result<D> {
         match self {
@@ -289,26 +1201,402 @@ impl DeviceActions for Device {
             Device::LB120(d) => d.send(msg),
             Device::KL110(d) => d.send(msg),
             Device::KP115(d) => d.send(msg),
+            Device::KP105(d) => d.send(msg),
+            Device::KP125(d) => d.send(msg),
             Device::Unknown(d) => d.send(msg),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::datatypes::tests::HS100_JSON_OFF;
-    use crate::protocol::mock::ProtocolMock;
+    fn send_with_timeout<D: DeserializeOwned>(&self, msg: &str, timeout: Duration) -> Result<D> {
+        match self {
+            Device::HS100(d) => d.send_with_timeout(msg, timeout),
+            Device::HS103(d) => d.send_with_timeout(msg, timeout),
+            Device::HS105(d) => d.send_with_timeout(msg, timeout),
+            Device::HS110(d) => d.send_with_timeout(msg, timeout),
+            Device::HS300(d) => d.send_with_timeout(msg, timeout),
+            Device::LB110(d) => d.send_with_timeout(msg, timeout),
+            Device::LB120(d) => d.send_with_timeout(msg, timeout),
+            Device::KL110(d) => d.send_with_timeout(msg, timeout),
+            Device::KP115(d) => d.send_with_timeout(msg, timeout),
+            Device::KP105(d) => d.send_with_timeout(msg, timeout),
+            Device::KP125(d) => d.send_with_timeout(msg, timeout),
+            Device::Unknown(d) => d.send_with_timeout(msg, timeout),
+        }
+    }
+}
 
-    #[test]
+/// The address and model of a device, which can be saved and used to rebuild
+/// the [`Device`](enum.Device.html) later without discovery
+///
+/// ```no_run
+/// use tplinker::{devices::DeviceDescriptor, discovery::discover};
+///
+/// let descriptors = discover()
+///     .unwrap()
+///     .iter()
+///     .map(|(addr, data)| DeviceDescriptor::from_data(*addr, data))
+///     .collect::<Vec<_>>();
+/// let saved = serde_json::to_string(&descriptors).unwrap();
+///
+/// let loaded: Vec<DeviceDescriptor> = serde_json::from_str(&saved).unwrap();
+/// let devices = loaded.iter().map(DeviceDescriptor::to_device).collect::<Vec<_>>();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceDescriptor {
+    /// Address of the device
+    pub addr: SocketAddr,
+    /// Model of the device, such as `HS110(UK)`
+    pub model: String,
+}
+
+impl DeviceDescriptor {
+    /// Make a descriptor from a raw `DeviceData`, such as one returned from
+    /// [`discover`](../discovery/fn.discover.html)
+    pub fn from_data(addr: SocketAddr, device_data: &DeviceData) -> Self {
+        Self {
+            addr,
+            model: device_data.sysinfo().model.clone(),
+        }
+    }
+
+    /// Build the device described, see [`Device::for_model`](enum.Device.html#method.for_model)
+    pub fn to_device(&self) -> Device {
+        Device::for_model(&self.model, self.addr)
+    }
+}
+
+/// Run a command against many devices in parallel
+///
+/// Each device's result is returned alongside its address, in the same order as
+/// `devices`, so that one unreachable device does not stop the others.
+///
+/// ```no_run
+/// use tplinker::{devices::broadcast_command, discovery::discover, devices::Device};
+///
+/// let devices = discover()
+///     .unwrap()
+///     .into_iter()
+///     .map(|(addr, data)| Device::from_data(addr, &data))
+///     .collect::<Vec<_>>();
+/// for (addr, result) in broadcast_command(&devices, Device::switch_off) {
+///     if let Err(err) = result {
+///         eprintln!("{}: {}", addr, err);
+///     }
+/// }
+/// ```
+pub fn broadcast_command<F, R>(devices: &[Device], command: F) -> Vec<(SocketAddr, Result<R>)>
+where
+    F: Fn(&Device) -> Result<R> + Sync,
+    R: Send,
+{
+    devices
+        .par_iter()
+        .map(|device| (device.address(), command(device)))
+        .collect()
+}
+
+/// Identify the devices at each of the given addresses in parallel
+///
+/// The result for every address is returned, in the same order, so that
+/// failures can be reported. See [`Device::identify`](enum.Device.html#method.identify).
+///
+/// ```no_run
+/// use tplinker::devices::status_many;
+///
+/// let addrs = ["192.168.0.10:9999".parse().unwrap(), "192.168.0.11:9999".parse().unwrap()];
+/// for (addr, result) in status_many(&addrs) {
+///     match result {
+///         Ok((_, sysinfo)) => println!("{}: {}", addr, sysinfo.alias),
+///         Err(err) => eprintln!("{}: {}", addr, err),
+///     }
+/// }
+/// ```
+pub fn status_many(addrs: &[SocketAddr]) -> Vec<(SocketAddr, Result<(Device, SysInfo)>)> {
+    addrs
+        .par_iter()
+        .map(|addr| (*addr, Device::identify(*addr)))
+        .collect()
+}
+
+/// Colour bulbs that are changed together
+///
+/// Bulbs are picked by the `is_color` flag in their system information rather
+/// than by model, so colour bulbs without their own device type are included.
+/// Each change is sent to every bulb in parallel so they change at the same
+/// time, and the result for each bulb is returned alongside its address.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use tplinker::{devices::ColourGroup, discovery::discover};
+///
+/// let bulbs = ColourGroup::from_discovered(&discover().unwrap());
+/// for (addr, result) in bulbs.set_hsv_with_transition(120, 100, 80, Duration::from_secs(1)) {
+///     if let Err(err) = result {
+///         eprintln!("{}: {}", addr, err);
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ColourGroup {
+    bulbs: Vec<(Device, (u16, u16))>,
+}
+
+impl ColourGroup {
+    /// Make a group of the colour bulbs in the results of [`discover`](../discovery/fn.discover.html)
+    pub fn from_discovered(devices: &[(SocketAddr, DeviceData)]) -> Self {
+        Self {
+            bulbs: devices
+                .iter()
+                .filter(|(_, data)| data.sysinfo().is_color())
+                .map(|(addr, data)| {
+                    (
+                        Device::from_data(*addr, data),
+                        data.sysinfo().color_temp_range(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Make a group of bulbs, checking each supports colour
+    ///
+    /// See [`Device::try_colour`](enum.Device.html#method.try_colour).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a device does not support colour or its system
+    /// information cannot be read.
+    pub fn new(bulbs: Vec<Device>) -> Result<Self> {
+        let ranges = bulbs
+            .par_iter()
+            .map(|bulb| match bulb.try_colour()? {
+                Some(colour) => Ok(colour.color_temp_range),
+                None => Err(Error::Unsupported(format!(
+                    "{} does not support colour",
+                    bulb.address()
+                ))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            bulbs: bulbs.into_iter().zip(ranges).collect(),
+        })
+    }
+
+    /// The addresses of the bulbs in the group
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.bulbs.iter().map(|(bulb, _)| bulb.address()).collect()
+    }
+
+    /// The number of bulbs in the group
+    pub fn len(&self) -> usize {
+        self.bulbs.len()
+    }
+
+    /// Whether there are no bulbs in the group
+    pub fn is_empty(&self) -> bool {
+        self.bulbs.is_empty()
+    }
+
+    /// Set hue, saturation and value (brightness) on every bulb,
+    /// see [`Colour::set_hsv`](../capabilities/trait.Colour.html#method.set_hsv)
+    pub fn set_hsv(
+        &self,
+        hue: u16,
+        saturation: u16,
+        brightness: u16,
+    ) -> Vec<(SocketAddr, Result<()>)> {
+        self.each(|bulb| bulb.set_hsv(hue, saturation, brightness))
+    }
+
+    /// Set hue, saturation and value (brightness) on every bulb, fading over the given duration,
+    /// see [`Colour::set_hsv_with_transition`](../capabilities/trait.Colour.html#method.set_hsv_with_transition)
+    pub fn set_hsv_with_transition(
+        &self,
+        hue: u16,
+        saturation: u16,
+        brightness: u16,
+        transition: Duration,
+    ) -> Vec<(SocketAddr, Result<()>)> {
+        self.each(|bulb| bulb.set_hsv_with_transition(hue, saturation, brightness, transition))
+    }
+
+    fn each<F>(&self, command: F) -> Vec<(SocketAddr, Result<()>)>
+    where
+        F: Fn(ColourDevice<'_>) -> Result<()> + Sync,
+    {
+        self.bulbs
+            .par_iter()
+            .map(|(bulb, color_temp_range)| {
+                let colour = ColourDevice {
+                    device: bulb,
+                    color_temp_range: *color_temp_range,
+                };
+                (bulb.address(), command(colour))
+            })
+            .collect()
+    }
+}
+
+/// Largest response accepted by [`probe`](fn.probe.html)
+const MAX_PROBE_RESPONSE: usize = DEFAULT_MAX_RESPONSE_SIZE;
+
+/// Check whether there is a responsive TPLink device at `addr`
+///
+/// This sends a system information query and returns `true` if a response that
+/// decrypts to valid JSON comes back within `timeout`. The response is not
+/// checked against [`SysInfo`](../datatypes/struct.SysInfo.html) so devices
+/// that this library cannot parse are still seen as alive.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use tplinker::devices::probe;
+///
+/// let alive = probe("192.168.0.99:9999".parse().unwrap(), Duration::from_secs(1)).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// A device that refuses the connection, times out, or replies with something
+/// else gives `Ok(false)`. Other I/O errors, such as the network being
+/// unreachable, are returned as `Err`.
+pub fn probe(addr: SocketAddr, timeout: Duration) -> Result<bool> {
+    match probe_response(addr, timeout) {
+        Ok(Some(response)) => Ok(serde_json::from_str::<serde_json::Value>(&response).is_ok()),
+        Ok(None) => Ok(false),
+        Err(err) => match err.kind() {
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::UnexpectedEof => Ok(false),
+            _ => Err(Error::from(err)),
+        },
+    }
+}
+
+fn probe_response(addr: SocketAddr, timeout: Duration) -> io::Result<Option<String>> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(&encrypt(r#"{"system":{"get_sysinfo":null}}"#))?;
+
+    let mut length = [0_u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length == 0 || length > MAX_PROBE_RESPONSE {
+        return Ok(None);
+    }
+    let mut response = vec![0_u8; length];
+    stream.read_exact(&mut response)?;
+    Ok(Some(decrypt(&mut response)))
+}
+
+/// A group of devices controlled together, such as all the lamps in a room
+///
+/// Commands are sent to every member in parallel using
+/// [`broadcast_command`](fn.broadcast_command.html) and the result for each
+/// member is returned.
+///
+/// ```no_run
+/// use tplinker::devices::{Device, DeviceGroup};
+///
+/// let group = DeviceGroup::new(vec![
+///     Device::for_model("LB110", "192.168.0.10:9999".parse().unwrap()),
+///     Device::for_model("LB120", "192.168.0.11:9999".parse().unwrap()),
+/// ]);
+/// let result = group.set_brightness(40);
+/// if !result.all_ok() {
+///     for (addr, err) in result.errors() {
+///         eprintln!("{}: {}", addr, err);
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DeviceGroup {
+    devices: Vec<Device>,
+}
+
+impl DeviceGroup {
+    /// Make a group from its member devices
+    pub fn new(devices: Vec<Device>) -> Self {
+        Self { devices }
+    }
+
+    /// The member devices
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// Run a command against every member
+    pub fn run<F, R>(&self, command: F) -> GroupResult<R>
+    where
+        F: Fn(&Device) -> Result<R> + Sync,
+        R: Send,
+    {
+        GroupResult {
+            results: broadcast_command(&self.devices, command),
+        }
+    }
+
+    /// Check whether each member is on
+    pub fn is_on(&self) -> GroupResult<bool> {
+        self.run(Device::is_on)
+    }
+
+    /// Switch every member on
+    pub fn switch_on(&self) -> GroupResult<()> {
+        self.run(Device::switch_on)
+    }
+
+    /// Switch every member off
+    pub fn switch_off(&self) -> GroupResult<()> {
+        self.run(Device::switch_off)
+    }
+
+    /// Set the percentage brightness of every member
+    pub fn set_brightness(&self, brightness: u16) -> GroupResult<()> {
+        self.run(|device| device.set_brightness(brightness))
+    }
+}
+
+/// The result of running a command against each member of a
+/// [`DeviceGroup`](struct.DeviceGroup.html)
+#[derive(Debug)]
+pub struct GroupResult<R> {
+    /// Each member's result alongside its address, in member order
+    pub results: Vec<(SocketAddr, Result<R>)>,
+}
+
+impl<R> GroupResult<R> {
+    /// Whether the command succeeded for every member
+    pub fn all_ok(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// The members the command failed for, with their errors
+    pub fn errors(&self) -> impl Iterator<Item = (SocketAddr, &Error)> {
+        self.results
+            .iter()
+            .filter_map(|(addr, result)| result.as_ref().err().map(|err| (*addr, err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datatypes::tests::{
+        HS100_JSON_OFF, HS110_JSON, HS300_JSON, KL130_JSON_COMMON_SYSTEM, LB110_JSON_OFF,
+        LB110_JSON_ON, LB120_JSON,
+    };
+    use crate::testing::MockProtocol;
+    use std::sync::mpsc;
+
+    #[test]
     fn test_raw_device_submit_success() {
         // arrange
-        let protocol = ProtocolMock::default();
-        protocol.set_send_return_value(Ok(String::from(HS100_JSON_OFF)));
-        let device = RawDevice {
-            addr: "0.0.0.0:9999".parse().unwrap(),
-            protocol,
-        };
+        let protocol = MockProtocol::new();
+        protocol.push_response(HS100_JSON_OFF);
+        let device = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol);
 
         // act
         let device_data: DeviceData = device.send("{}").unwrap();
@@ -318,26 +1606,699 @@ mod tests {
     }
 
     #[test]
-    fn test_raw_device_submit_failure() {
-        let protocol = ProtocolMock::default();
-        protocol.set_send_return_value(Ok(String::from("invalid")));
-        let device = RawDevice {
-            addr: "0.0.0.0:9999".parse().unwrap(),
+    fn raw_device_detect_emeter_type_from_sysinfo() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(LB110_JSON_ON);
+        let device = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol.clone());
+
+        assert_eq!(
+            device.detect_emeter_type().unwrap(),
+            "smartlife.iot.common.emeter"
+        );
+        assert_eq!(
+            device.detect_emeter_type().unwrap(),
+            "smartlife.iot.common.emeter"
+        );
+        assert_eq!(protocol.sent().len(), 1);
+    }
+
+    #[test]
+    fn raw_device_detect_emeter_type_retries_after_error() {
+        let protocol = MockProtocol::new();
+        protocol.push_io_error(io::ErrorKind::TimedOut);
+        protocol.push_response(HS110_JSON);
+        let device = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol.clone());
+
+        assert!(matches!(device.detect_emeter_type(), Err(Error::IO(_))));
+        assert_eq!(device.detect_emeter_type().unwrap(), "emeter");
+        assert_eq!(protocol.sent().len(), 2);
+    }
+
+    #[test]
+    fn device_try_emeter() {
+        let addr = "10.0.0.1:9999".parse().unwrap();
+
+        let plug = Device::for_model("HS110(UK)", addr);
+        assert_eq!(plug.try_emeter().unwrap().unwrap().emeter_type(), "emeter");
+        let bulb = Device::for_model("KL110(UK)", addr);
+        assert_eq!(
+            bulb.try_emeter().unwrap().unwrap().emeter_type(),
+            "smartlife.iot.common.emeter"
+        );
+        assert!(Device::for_model("HS100(UK)", addr)
+            .try_emeter()
+            .unwrap()
+            .is_none());
+
+        let (unknown_addr, _) = command_server(vec![HS100_JSON_OFF.to_string()]);
+        let unknown = Device::for_model("XX999", unknown_addr);
+        assert_eq!(
+            unknown.try_emeter().unwrap().unwrap().emeter_type(),
+            "emeter"
+        );
+    }
+
+    #[test]
+    fn raw_device_detect_emeter_type_tries_both() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(&HS110_JSON.replace("IOT.SMARTPLUGSWITCH", "IOT.SOMETHINGNEW"));
+        protocol.push_response(r#"{"emeter":{"err_code":-1,"err_msg":"module not support"}}"#);
+        protocol.push_response(
+            r#"{"smartlife.iot.common.emeter":{"get_realtime":{"power_mw":0,"err_code":0}}}"#,
+        );
+        let device = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol.clone());
+
+        assert_eq!(
+            device.detect_emeter_type().unwrap(),
+            "smartlife.iot.common.emeter"
+        );
+        assert_eq!(
+            protocol.sent()[1..],
+            [
+                r#"{"emeter":{"get_realtime":null}}"#,
+                r#"{"smartlife.iot.common.emeter":{"get_realtime":null}}"#,
+            ]
+        );
+    }
+
+    fn probe_server(response: Vec<u8>) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            if let Ok((mut socket, _)) = listener.accept() {
+                let mut request = [0_u8; 64];
+                let _ = socket.read(&mut request);
+                socket.write_all(&response).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn probe_device() {
+        let timeout = Duration::from_secs(1);
+
+        let addr = probe_server(encrypt(r#"{"system":{"get_sysinfo":{"model":"XX999"}}}"#));
+        assert!(probe(addr, timeout).unwrap());
+
+        let addr = probe_server(encrypt("not json"));
+        assert!(!probe(addr, timeout).unwrap());
+
+        let addr = probe_server(vec![0, 0]);
+        assert!(!probe(addr, timeout).unwrap());
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(!probe(closed, timeout).unwrap());
+    }
+
+    #[test]
+    fn device_child_sysinfo() {
+        let addr = probe_server(encrypt(HS300_JSON));
+        let strip = Device::for_model("HS300(US)", addr);
+
+        let child = strip.child_sysinfo(0).unwrap();
+        assert_eq!(child.id, "00");
+        assert_eq!(child.on_time, 2357786);
+
+        let plug = Device::for_model("HS100(UK)", addr);
+        assert!(plug.child_sysinfo(0).unwrap_err().is_unsupported());
+    }
+
+    #[test]
+    fn status_many_keeps_errors() {
+        let found = probe_server(encrypt(HS110_JSON));
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let results = status_many(&[found, closed]);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, found);
+        match &results[0].1 {
+            Ok((Device::HS110(_), sysinfo)) => assert_eq!(sysinfo.model, "HS110(UK)"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(results[1].0, closed);
+        assert!(results[1].1.is_err());
+    }
+
+    #[test]
+    fn resolve_addresses() {
+        let expected: SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        assert_eq!(resolve_address("127.0.0.1:9999").unwrap(), expected);
+        assert_eq!(resolve_address("127.0.0.1").unwrap(), expected);
+        assert_eq!(
+            resolve_address("::1").unwrap(),
+            "[::1]:9999".parse().unwrap()
+        );
+        assert_eq!(resolve_address("localhost:1234").unwrap().port(), 1234);
+        assert_eq!(resolve_address("localhost").unwrap().port(), 9999);
+        assert!(resolve_address("localhost:port").is_err());
+        assert_eq!(
+            resolve_address_with_port("127.0.0.1", 80).unwrap(),
+            "127.0.0.1:80".parse().unwrap()
+        );
+        assert_eq!(
+            resolve_address_with_port("127.0.0.1:9999", 80).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn raw_device_with_port() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(HS100_JSON_OFF);
+        let device = RawDevice::with_protocol("127.0.0.1:9999".parse().unwrap(), protocol.clone())
+            .with_port(20002);
+
+        device.sysinfo().unwrap();
+
+        assert_eq!(device.port(), 20002);
+        assert_eq!(protocol.requests()[0].0, "127.0.0.1:20002".parse().unwrap());
+    }
+
+    #[test]
+    fn child_context_is_added_to_every_command() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(r#"{"emeter":{"get_realtime":{"power_mw":1200,"err_code":0}}}"#);
+        protocol.push_response(r#"{"system":{"set_dev_alias":{"err_code":0}}}"#);
+        let strip = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol.clone());
+        let outlet = strip.with_child_context("800601");
+
+        outlet.get_emeter_realtime().unwrap();
+        outlet.set_alias("Kettle").unwrap();
+
+        assert_eq!(
+            protocol.sent(),
+            vec![
+                r#"{"context":{"child_ids":["800601"]},"emeter":{"get_realtime":null}}"#,
+                r#"{"context":{"child_ids":["800601"]},"system":{"set_dev_alias":{"alias":"Kettle"}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn child_context_switch_reads_outlet_state() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(HS300_JSON);
+        protocol.push_response(HS300_JSON);
+        let strip = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol);
+
+        assert!(strip.with_child_context("01").is_on().unwrap());
+        assert!(strip.with_child_context("07").is_on().is_err());
+    }
+
+    #[test]
+    fn power_strip_is_on_by_child_id() {
+        // the first child listed is "01", switch off the second, which is "00"
+        let json = HS300_JSON.replace(
+            "\"id\": \"00\",\n              \"state\": 1",
+            "\"id\": \"00\",\n              \"state\": 0",
+        );
+        assert_ne!(json, HS300_JSON);
+        let protocol = MockProtocol::new();
+        protocol.push_response(&json);
+        protocol.push_response(&json);
+        let strip = HS300::from_raw(RawDevice::with_protocol(
+            "0.0.0.0:9999".parse().unwrap(),
             protocol,
-        };
+        ));
 
-        assert!(device.send::<DeviceData>("{}").is_err());
+        assert!(MultiSwitch::is_on(&strip, 1).unwrap());
+        assert!(!MultiSwitch::is_on(&strip, 0).unwrap());
     }
 
     #[test]
-    fn test_raw_device_location() {
-        let protocol = ProtocolMock::default();
-        protocol.set_send_return_value(Ok(String::from(HS100_JSON_OFF)));
-        let device = RawDevice {
-            addr: "0.0.0.0:9999".parse().unwrap(),
+    fn power_strip_outlet_uptime() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(HS300_JSON);
+        protocol.push_response(HS300_JSON);
+        protocol.push_response(HS300_JSON);
+        let strip = HS300::from_raw(RawDevice::with_protocol(
+            "0.0.0.0:9999".parse().unwrap(),
             protocol,
-        };
+        ));
+
+        // outlet "01" is listed first and "00" second
+        assert_eq!(strip.uptime(1).unwrap(), Duration::from_secs(47724));
+        assert_eq!(strip.uptime(0).unwrap(), Duration::from_secs(2357786));
+        assert!(strip.uptime(6).is_err());
+    }
+
+    #[test]
+    fn test_relay_device_switch() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(HS100_JSON_OFF);
+        let raw = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol);
+
+        assert!(!RelayDevice { raw: &raw }.is_on().unwrap());
+    }
+
+    #[test]
+    fn test_bulb_is_on() {
+        for (json, expected) in &[(LB110_JSON_ON, true), (LB110_JSON_OFF, false)] {
+            let protocol = MockProtocol::new();
+            protocol.push_response(json);
+            let device = LB110::from_raw(RawDevice::with_protocol(
+                "0.0.0.0:9999".parse().unwrap(),
+                protocol,
+            ));
+
+            assert_eq!(device.is_on().unwrap(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_raw_device_submit_failure() {
+        let protocol = MockProtocol::new();
+        protocol.push_response("invalid");
+        let device = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol);
+
+        match device.send::<DeviceData>("{}") {
+            Err(Error::ResponseParse { raw, .. }) => assert_eq!(raw, "invalid"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_raw_device_location() {
+        let protocol = MockProtocol::new();
+        protocol.push_response(HS100_JSON_OFF);
+        let device = RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol);
 
         assert_eq!((3456.0, 123.0), device.location().unwrap());
     }
+
+    #[test]
+    fn test_device_capabilities() {
+        let addr = "0.0.0.0:9999".parse().unwrap();
+        for json in &[HS110_JSON, HS300_JSON, LB120_JSON] {
+            let data: DeviceData = serde_json::from_str(json).unwrap();
+            let device = Device::from_data(addr, &data);
+
+            assert_eq!(
+                device.capabilities(),
+                DeviceCapabilities::from_sysinfo(data.sysinfo())
+            );
+        }
+
+        let data: DeviceData = serde_json::from_str(HS110_JSON).unwrap();
+        let capabilities = Device::from_data(addr, &data).capabilities();
+        assert!(capabilities.switchable);
+        assert!(capabilities.emeter);
+        assert!(!capabilities.dimmable);
+    }
+
+    #[test]
+    fn test_device_has_emeter() {
+        let addr = "10.0.0.1:9999".parse().unwrap();
+        for (model, expected) in &[
+            ("HS100(UK)", false),
+            ("HS110(UK)", true),
+            ("HS300(US)", true),
+            ("KP115(UK)", true),
+            ("LB110(EU)", true),
+            ("XX999", false),
+        ] {
+            assert_eq!(
+                Device::for_model(model, addr).has_emeter(),
+                *expected,
+                "{}",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn test_device_address() {
+        let addr: SocketAddr = "10.0.0.1:9999".parse().unwrap();
+        assert_eq!(Device::for_model("HS110(UK)", addr).address(), addr);
+        assert_eq!(Device::for_model("XX999", addr).address(), addr);
+        assert_eq!(RawDevice::from_addr(addr).address(), addr);
+    }
+
+    #[test]
+    fn test_device_for_model_normalises() {
+        let addr = "10.0.0.1:9999".parse().unwrap();
+        assert!(matches!(
+            Device::for_model(" hs110(uk) ", addr),
+            Device::HS110(_)
+        ));
+        assert!(matches!(
+            Device::for_model("Kp115(US)", addr),
+            Device::KP115(_)
+        ));
+
+        let data: DeviceData =
+            serde_json::from_str(&LB110_JSON_OFF.replace("LB110(EU)", "lb110(eu)")).unwrap();
+        assert!(LB110::try_from((addr, &data)).is_ok());
+    }
+
+    #[test]
+    fn test_device_from_data_kp_plugs() {
+        let addr = "0.0.0.0:9999".parse().unwrap();
+        let data: DeviceData =
+            serde_json::from_str(&HS100_JSON_OFF.replace("HS100(UK)", "KP105(UK)")).unwrap();
+        let device = Device::from_data(addr, &data);
+        assert!(matches!(device, Device::KP105(_)));
+        assert_eq!(
+            device.capabilities(),
+            DeviceCapabilities::from_sysinfo(data.sysinfo())
+        );
+
+        let data: DeviceData =
+            serde_json::from_str(&HS110_JSON.replace("HS110(UK)", "KP125(US)")).unwrap();
+        let device = Device::from_data(addr, &data);
+        assert!(matches!(device, Device::KP125(_)));
+        assert_eq!(
+            device.capabilities(),
+            DeviceCapabilities::from_sysinfo(data.sysinfo())
+        );
+    }
+
+    #[test]
+    fn raw_device_refuses_while_updating() {
+        let protocol = MockProtocol::new();
+        let updating = HS100_JSON_OFF.replace(r#""updating": 0"#, r#""updating": 1"#);
+        protocol.push_response(&updating);
+        protocol.push_response(&updating);
+        let device = HS100::from_raw(
+            RawDevice::with_protocol("0.0.0.0:9999".parse().unwrap(), protocol.clone())
+                .refuse_while_updating(true),
+        );
+
+        assert!(matches!(device.switch_on(), Err(Error::Updating)));
+        assert!(!device.is_on().unwrap());
+        assert_eq!(protocol.sent().len(), 2);
+
+        protocol.push_response(HS100_JSON_OFF);
+        protocol.push_response(r#"{"system":{"set_relay_state":{"err_code":0}}}"#);
+        device.switch_on().unwrap();
+        assert_eq!(
+            protocol.sent()[3],
+            r#"{"system":{"set_relay_state":{"state":1}}}"#
+        );
+    }
+
+    #[test]
+    fn colour_group_from_discovered() {
+        let color = |addr: &str, json: &str| {
+            (
+                addr.parse().unwrap(),
+                serde_json::from_str(&json.replace(r#""is_color": 0"#, r#""is_color": 1"#))
+                    .unwrap(),
+            )
+        };
+        let devices = vec![
+            color("10.0.0.1:9999", LB120_JSON),
+            (
+                "10.0.0.2:9999".parse().unwrap(),
+                serde_json::from_str(LB110_JSON_ON).unwrap(),
+            ),
+            color("10.0.0.3:9999", LB110_JSON_OFF),
+            (
+                "10.0.0.4:9999".parse().unwrap(),
+                serde_json::from_str(HS100_JSON_OFF).unwrap(),
+            ),
+        ];
+
+        let group = ColourGroup::from_discovered(&devices);
+
+        assert_eq!(
+            group.addresses(),
+            vec![
+                "10.0.0.1:9999".parse::<SocketAddr>().unwrap(),
+                "10.0.0.3:9999".parse().unwrap()
+            ]
+        );
+    }
+
+    /// Serve one response per connection, sending each command received to the channel
+    fn command_server(responses: Vec<String>) -> (SocketAddr, mpsc::Receiver<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut length = [0_u8; 4];
+                socket.read_exact(&mut length).unwrap();
+                let mut msg = vec![0_u8; u32::from_be_bytes(length) as usize];
+                socket.read_exact(&mut msg).unwrap();
+                let _ = sender.send(decrypt(&mut msg));
+                socket.write_all(&encrypt(&response)).unwrap();
+            }
+        });
+        (addr, received)
+    }
+
+    fn colour_bulb(json: &str) -> String {
+        json.replace(r#""is_color": 0"#, r#""is_color": 1"#)
+    }
+
+    #[test]
+    fn colour_group_set_hsv() {
+        let (first, first_received) = command_server(vec![LB110_JSON_ON.to_string()]);
+        let (second, second_received) = command_server(vec![LB110_JSON_ON.to_string()]);
+        let data: DeviceData = serde_json::from_str(&colour_bulb(LB110_JSON_ON)).unwrap();
+        let group = ColourGroup::from_discovered(&[(first, data.clone()), (second, data)]);
+
+        let results = group.set_hsv(120, 100, 80);
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        for received in &[first_received, second_received] {
+            assert_eq!(
+                received.recv().unwrap(),
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"brightness":80,"hue":120,"saturation":100}}}"#
+            );
+        }
+        assert!(group
+            .set_hsv(400, 100, 80)
+            .iter()
+            .all(|(_, result)| result.is_err()));
+    }
+
+    #[test]
+    fn colour_group_checks_colour() {
+        let (colour_addr, _) = command_server(vec![colour_bulb(LB110_JSON_ON)]);
+        let (white_addr, _) = command_server(vec![LB110_JSON_ON.to_string()]);
+
+        let group = ColourGroup::new(vec![Device::for_model("XX999", colour_addr)]).unwrap();
+        assert_eq!(group.addresses(), vec![colour_addr]);
+
+        let err = ColourGroup::new(vec![Device::for_model("LB110(EU)", white_addr)]).unwrap_err();
+        assert!(err.is_unsupported());
+        assert!(Device::for_model("HS100(UK)", white_addr)
+            .try_colour()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn device_try_color_temperature() {
+        let (bulb_addr, received) = command_server(vec![
+            LB120_JSON.to_string(),
+            String::from(
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1,"mode":"normal","hue":0,"saturation":0,"color_temp":4500,"brightness":100,"err_code":0}}}"#,
+            ),
+        ]);
+        let (white_addr, _) = command_server(vec![LB110_JSON_ON.to_string()]);
+
+        let bulb = Device::for_model("LB120(EU)", bulb_addr);
+        let bulb = bulb.try_color_temperature().unwrap().unwrap();
+        bulb.set_color_temp(4500).unwrap();
+        assert_eq!(
+            received.iter().collect::<Vec<_>>(),
+            vec![
+                r#"{"system":{"get_sysinfo":null}}"#,
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"color_temp":4500}}}"#,
+            ]
+        );
+
+        let kl130 = KL130_JSON_COMMON_SYSTEM.replace(
+            r#""is_variable_color_temp": 0"#,
+            r#""is_variable_color_temp": 1"#,
+        );
+        let (kl130_addr, received) = command_server(vec![
+            kl130,
+            String::from(
+                r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"on_off":1,"mode":"normal","hue":0,"saturation":0,"color_temp":2500,"brightness":100,"err_code":0}}}"#,
+            ),
+        ]);
+        let kl130 = Device::for_model("KL130(EU)", kl130_addr);
+        let kl130 = kl130.try_color_temperature().unwrap().unwrap();
+        assert_eq!(kl130.color_temp_range(), (2500, 9000));
+        assert!(kl130.set_color_temp(9001).is_err());
+        kl130.set_color_temp(2500).unwrap();
+        assert_eq!(
+            received.iter().nth(1).unwrap(),
+            r#"{"smartlife.iot.smartbulb.lightingservice":{"transition_light_state":{"color_temp":2500}}}"#
+        );
+
+        assert!(Device::for_model("LB110(EU)", white_addr)
+            .try_color_temperature()
+            .unwrap()
+            .is_none());
+        assert!(Device::for_model("HS100(UK)", white_addr)
+            .try_color_temperature()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn device_try_dimmer_config() {
+        let dimmer = HS100_JSON_OFF.replace(
+            r#""relay_state": 0,"#,
+            r#""relay_state": 0, "brightness": 50,"#,
+        );
+        let (dimmer_addr, _) = command_server(vec![
+            dimmer,
+            String::from(r#"{"smartlife.iot.dimmer":{"set_min_threshold":{"err_code":0}}}"#),
+        ]);
+        let (plug_addr, _) = command_server(vec![HS100_JSON_OFF.to_string()]);
+
+        let dimmer = Device::for_model("HS220(US)", dimmer_addr);
+        dimmer
+            .try_dimmer_config()
+            .unwrap()
+            .unwrap()
+            .set_min_threshold(10)
+            .unwrap();
+        let plug = Device::for_model("XX999", plug_addr);
+        assert!(plug.try_dimmer_config().unwrap().is_none());
+        let bulb = Device::for_model("LB110(EU)", plug_addr);
+        assert!(bulb.try_dimmer_config().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_device_try_from_data() {
+        let addr = "10.0.0.1:9999".parse().unwrap();
+        let data: DeviceData = serde_json::from_str(LB110_JSON_OFF).unwrap();
+
+        let device = LB110::try_from((addr, &data)).unwrap();
+        assert_eq!(device.raw.address(), addr);
+
+        let err = HS100::try_from((addr, &data)).unwrap_err();
+        assert_eq!(err.to_string(), "LB110(EU) is not a HS100");
+    }
+
+    #[test]
+    fn test_device_not_switchable() {
+        let device = Device::HS300(HS300::from_addr("0.0.0.0:9999".parse().unwrap()));
+
+        assert!(device.switch_on().is_err());
+        assert!(device.set_brightness(50).is_err());
+    }
+
+    #[test]
+    fn test_broadcast_command() {
+        let devices = vec![
+            Device::HS100(HS100::from_addr("10.0.0.1:9999".parse().unwrap())),
+            Device::Unknown(RawDevice::from_addr("10.0.0.2:9999".parse().unwrap())),
+        ];
+
+        let results = broadcast_command(&devices, |device| match device {
+            Device::Unknown(_) => Err(Error::from("unknown")),
+            _ => Ok(()),
+        });
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "10.0.0.1:9999".parse().unwrap());
+        assert!(results[0].1.is_ok());
+        assert_eq!(results[1].0, "10.0.0.2:9999".parse().unwrap());
+        assert!(results[1].1.is_err());
+    }
+
+    #[test]
+    fn device_for_model() {
+        let addr: SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        assert!(matches!(
+            Device::for_model("HS110(UK)", addr),
+            Device::HS110(_)
+        ));
+        assert!(matches!(
+            Device::for_model("KP115(US)", addr),
+            Device::KP115(_)
+        ));
+        assert!(matches!(
+            Device::for_model("KP105(UK)", addr),
+            Device::KP105(_)
+        ));
+        assert!(matches!(
+            Device::for_model("KP125(US)", addr),
+            Device::KP125(_)
+        ));
+        assert!(matches!(
+            Device::for_model("LB120(EU)", addr),
+            Device::LB120(_)
+        ));
+        assert!(matches!(
+            Device::for_model("XX999", addr),
+            Device::Unknown(_)
+        ));
+    }
+
+    #[test]
+    fn group_result_all_ok() {
+        let addr: SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        let ok = GroupResult {
+            results: vec![(addr, Ok(()))],
+        };
+        assert!(ok.all_ok());
+        assert_eq!(ok.errors().count(), 0);
+
+        let failed = GroupResult {
+            results: vec![(addr, Ok(())), (addr, Err(Error::from("unreachable")))],
+        };
+        assert!(!failed.all_ok());
+        assert_eq!(failed.errors().count(), 1);
+    }
+
+    #[test]
+    fn empty_group() {
+        assert!(DeviceGroup::default().switch_on().all_ok());
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)] // the hash only uses the address, which never changes
+    fn device_equality_is_address_based() {
+        let addr: SocketAddr = "10.0.0.1:9999".parse().unwrap();
+        let other: SocketAddr = "10.0.0.2:9999".parse().unwrap();
+
+        assert_eq!(
+            Device::for_model("HS100(UK)", addr),
+            Device::for_model("HS110(UK)", addr)
+        );
+        assert_ne!(
+            Device::for_model("HS100(UK)", addr),
+            Device::for_model("HS100(UK)", other)
+        );
+
+        let devices: std::collections::HashSet<Device> = vec![
+            Device::for_model("HS100(UK)", addr),
+            Device::for_model("XX999", addr),
+            Device::for_model("HS100(UK)", other),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(devices.len(), 2);
+    }
+
+    #[test]
+    fn device_descriptor_round_trip() {
+        let data: DeviceData = serde_json::from_str(HS110_JSON).unwrap();
+        let descriptor = DeviceDescriptor::from_data("10.0.0.1:9999".parse().unwrap(), &data);
+
+        let json = serde_json::to_string(&descriptor).unwrap();
+        assert_eq!(json, r#"{"addr":"10.0.0.1:9999","model":"HS110(UK)"}"#);
+        let loaded: DeviceDescriptor = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, descriptor);
+        assert!(matches!(loaded.to_device(), Device::HS110(_)));
+    }
 }
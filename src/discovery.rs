@@ -18,20 +18,22 @@
 //! }
 //! ```
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::mpsc,
     time::Duration,
 };
 
 use crossbeam::thread;
 use if_addrs::{IfAddr, Interface};
+use log::{trace, warn};
 
-use crate::error::Error;
+use crate::error::{DiscoveryError, Error};
 
 use crate::{datatypes::DeviceData, error::Result, protocol};
 
 // TODO: consider moving this to query builder
-const QUERY: &str = r#"{
+pub(crate) const QUERY: &str = r#"{
     "system": {"get_sysinfo": null},
     "emeter": {"get_realtime": null},
     "smartlife.iot.dimmer": {"get_dimmer_parameters": null},
@@ -39,6 +41,12 @@ const QUERY: &str = r#"{
     "smartlife.iot.smartbulb.lightingservice": {"get_light_state": null}
 }"#;
 
+/// Largest discovery response read, the most an IPv4 UDP datagram can hold
+///
+/// Power strips with many outlets send responses well over 4KB. No response can
+/// be larger, so none are truncated.
+const MAX_RESPONSE_SIZE: usize = 65_507;
+
 fn can_interface_broadcast(iface: Interface) -> Option<(Ipv4Addr, Ipv4Addr)> {
     match iface.addr {
         IfAddr::V4(addr) => match (addr.ip.is_loopback(), addr.broadcast) {
@@ -49,58 +57,534 @@ fn can_interface_broadcast(iface: Interface) -> Option<(Ipv4Addr, Ipv4Addr)> {
     }
 }
 
-fn discover_on_interface(
+/// Options for discovering devices, see [`with_options`](fn.with_options.html)
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use tplinker::discovery::{with_options, DiscoveryOptions};
+///
+/// let options = DiscoveryOptions::new()
+///     .timeout(Some(Duration::from_secs(5)))
+///     .source_port(50000)
+///     .attempts(5)
+///     .attempt_delay(Duration::from_millis(200));
+/// let discovery = with_options(&options).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DiscoveryOptions {
     timeout: Option<Duration>,
+    source_port: u16,
+    port: u16,
+    attempts: u32,
+    attempt_delay: Duration,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(3)),
+            source_port: 0,
+            port: protocol::DEFAULT_PORT,
+            attempts: 3,
+            attempt_delay: Duration::from_secs(0),
+        }
+    }
+}
+
+impl DiscoveryOptions {
+    /// Make the default options, a timeout of 3 seconds, any source port and
+    /// three queries sent back to back
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how long to wait for responses, `None` waits forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the local UDP port to send from, for networks where only specific
+    /// source ports are allowed through the firewall
+    ///
+    /// The default of `0` lets the operating system choose.
+    pub fn source_port(mut self, source_port: u16) -> Self {
+        self.source_port = source_port;
+        self
+    }
+
+    /// Set the port devices are listening on, the default is
+    /// [`DEFAULT_PORT`](../protocol/constant.DEFAULT_PORT.html)
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Set how many times the discovery query is sent
+    ///
+    /// Queries are sent over UDP and may be dropped, sending more than one makes
+    /// it more likely that every device hears at least one. The default is `3`.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Set how long to wait between sending each query
+    ///
+    /// On congested networks queries sent back to back may all be dropped. The
+    /// default is to not wait.
+    pub fn attempt_delay(mut self, attempt_delay: Duration) -> Self {
+        self.attempt_delay = attempt_delay;
+        self
+    }
+}
+
+/// Send the discovery query as many times as the options ask for
+fn send_queries(
+    options: &DiscoveryOptions,
+    udp_socket: &UdpSocket,
+    dest_socket_addr: SocketAddr,
+    request: &[u8],
+) {
+    for attempt in 0..options.attempts {
+        if attempt > 0 && options.attempt_delay > Duration::from_secs(0) {
+            std::thread::sleep(options.attempt_delay);
+        }
+        let send_res = udp_socket.send_to(&request[4..request.len()], dest_socket_addr);
+        trace!(
+            "Sent discovery query {} to {}: {:?}",
+            attempt + 1,
+            dest_socket_addr,
+            send_res
+        );
+    }
+}
+
+fn discover_on_interface(
+    options: &DiscoveryOptions,
     ip: Ipv4Addr,
     broadcast: Ipv4Addr,
     request: &[u8],
 ) -> Result<HashMap<SocketAddr, DeviceData>> {
-    let socket_addr = SocketAddr::new(IpAddr::V4(ip), 0);
+    let mut devices = HashMap::new();
+    discover_on_interface_with(options, ip, broadcast, request, |addr, device_data| {
+        devices.insert(addr, device_data);
+    })?;
+    Ok(devices)
+}
+
+/// Search one interface, calling `found` with each device as it responds
+fn discover_on_interface_with<F>(
+    options: &DiscoveryOptions,
+    ip: Ipv4Addr,
+    broadcast: Ipv4Addr,
+    request: &[u8],
+    found: F,
+) -> Result<()>
+where
+    F: FnMut(SocketAddr, DeviceData),
+{
+    let socket_addr = SocketAddr::new(IpAddr::V4(ip), options.source_port);
     let udp_socket = UdpSocket::bind(socket_addr)?;
     udp_socket.set_broadcast(true)?;
-    udp_socket.set_read_timeout(timeout)?;
-    let dest_socket_addr = SocketAddr::new(IpAddr::V4(broadcast), 9999);
-    for _ in 0..3 {
-        let _send_res = udp_socket.send_to(&request[4..request.len()], dest_socket_addr);
-    }
+    let dest_socket_addr = SocketAddr::new(IpAddr::V4(broadcast), options.port);
+    discover_on_socket(options, &udp_socket, dest_socket_addr, request, found)
+}
 
-    let mut buf = [0_u8; 4096];
-    let mut devices = HashMap::new();
+/// Query and read responses on an already bound socket until the timeout
+fn discover_on_socket<F>(
+    options: &DiscoveryOptions,
+    udp_socket: &UdpSocket,
+    dest_socket_addr: SocketAddr,
+    request: &[u8],
+    mut found: F,
+) -> Result<()>
+where
+    F: FnMut(SocketAddr, DeviceData),
+{
+    udp_socket.set_read_timeout(options.timeout)?;
+    send_queries(options, udp_socket, dest_socket_addr, request);
+
+    let mut buf = vec![0_u8; MAX_RESPONSE_SIZE];
     while let Ok((size, addr)) = udp_socket.recv_from(&mut buf) {
         let data = protocol::decrypt(&mut buf[0..size]);
-        if let Ok(device_data) = serde_json::from_str::<DeviceData>(&data) {
-            devices.insert(addr, device_data);
+        trace!("Discovery response from {}: {}", addr, data);
+        match serde_json::from_str::<DeviceData>(&data) {
+            Ok(device_data) => found(addr, device_data),
+            Err(err) => warn!("Dropped discovery response from {}: {}", addr, err),
         }
     }
-    Ok(devices)
+    Ok(())
 }
 
-/// Discover TPLink smart devices on the local network
+/// An iterator over devices as they respond to discovery, see
+/// [`discover_stream`](fn.discover_stream.html)
+#[derive(Debug)]
+pub struct DiscoveryStream {
+    receiver: mpsc::Receiver<(SocketAddr, DeviceData)>,
+    seen: HashSet<String>,
+}
+
+impl Iterator for DiscoveryStream {
+    type Item = (SocketAddr, DeviceData);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (addr, data) = self.receiver.recv().ok()?;
+            if self.seen.insert(data.sysinfo().device_id.clone()) {
+                return Some((addr, data));
+            }
+        }
+    }
+}
+
+/// Discover TPLink smart devices on the local network, yielding each device
+/// as soon as it responds
+///
+/// The iterator ends once the default timeout of 3 seconds has passed on
+/// every interface. Devices seen on more than one interface are only yielded
+/// the first time.
+///
+/// ```no_run
+/// for (addr, data) in tplinker::discovery::discover_stream().unwrap() {
+///     println!("{}\t{}", addr, data.sysinfo().alias);
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Will return `Err` if [`stream_with_options`](fn.stream_with_options.html)
+/// returns an `Err`.
+pub fn discover_stream() -> Result<DiscoveryStream> {
+    stream_with_options(&DiscoveryOptions::new())
+}
+
+/// Discover TPLink smart devices on the local network using the given
+/// options, yielding each device as soon as it responds
+///
+/// Interfaces that fail are skipped. A timeout of `None` means the iterator
+/// never ends.
 ///
 /// # Errors
 ///
-/// Will return `Err` if there is a `io::Error` communicating with the device or
-/// a problem decoding the response.
+/// Will return `Err` if the local interfaces cannot be listed or there are no
+/// interfaces that can broadcast.
+pub fn stream_with_options(options: &DiscoveryOptions) -> Result<DiscoveryStream> {
+    let request = protocol::encrypt(QUERY);
+    let interfaces = if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter_map(can_interface_broadcast)
+        .collect::<Vec<_>>();
+    if interfaces.is_empty() {
+        return Err(DiscoveryError::NoBroadcastInterfaces.into());
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    for (ip, broadcast) in interfaces {
+        let sender = sender.clone();
+        let options = options.clone();
+        let request = request.clone();
+        std::thread::spawn(move || {
+            discover_on_interface_with(&options, ip, broadcast, &request, |addr, data| {
+                let _ = sender.send((addr, data));
+            })
+        });
+    }
+    Ok(DiscoveryStream {
+        receiver,
+        seen: HashSet::new(),
+    })
+}
+
+/// A device found during discovery along with where it was found
+#[derive(Debug, Clone)]
+pub struct DiscoveredDevice {
+    /// Address the device responded from
+    pub addr: SocketAddr,
+    /// Device data returned in the discovery response
+    pub data: DeviceData,
+    /// Name of the local interface the response was received on
+    pub via_interface: Option<String>,
+    /// Local IP address the response was received on
+    pub local_ip: Option<IpAddr>,
+}
+
+impl DiscoveredDevice {
+    /// The outlets of a power strip, see [`outlets`](fn.outlets.html)
+    pub fn outlets(&self) -> Vec<DiscoveredOutlet> {
+        outlets(self.addr, &self.data)
+    }
+}
+
+/// A single outlet of a power strip found during discovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredOutlet {
+    /// Address of the power strip
+    pub addr: SocketAddr,
+    /// Index of the outlet, as used by [`MultiSwitch`](../capabilities/trait.MultiSwitch.html)
+    pub index: usize,
+    /// Full id of the outlet, as used by
+    /// [`RawDevice::with_child_context`](../devices/struct.RawDevice.html#method.with_child_context)
+    pub child_id: String,
+    /// Name of the outlet
+    pub alias: String,
+    /// Whether the outlet was switched on
+    pub is_on: bool,
+    /// How long the outlet had been switched on for, zero if it was off
+    pub uptime: Duration,
+}
+
+/// List the outlets of a discovered power strip so they can be shown and
+/// controlled individually
+///
+/// Returns an empty list for devices without outlets.
+///
+/// ```no_run
+/// for (addr, data) in tplinker::discover().unwrap() {
+///     for outlet in tplinker::discovery::outlets(addr, &data) {
+///         println!("{} #{}\t{}", addr, outlet.index, outlet.alias);
+///     }
+/// }
+/// ```
+pub fn outlets(addr: SocketAddr, data: &DeviceData) -> Vec<DiscoveredOutlet> {
+    let sysinfo = data.sysinfo();
+    sysinfo
+        .children
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(position, child)| {
+            let child_id = if child.id.starts_with(&sysinfo.device_id) {
+                child.id.clone()
+            } else {
+                format!("{}{}", sysinfo.device_id, child.id)
+            };
+            let index = child_id
+                .get(child_id.len().saturating_sub(2)..)
+                .and_then(|suffix| suffix.parse().ok())
+                .unwrap_or(position);
+            DiscoveredOutlet {
+                addr,
+                index,
+                child_id,
+                alias: child.alias.clone(),
+                is_on: child.is_on(),
+                uptime: child.uptime(),
+            }
+        })
+        .collect()
+}
+
+/// Remove devices seen more than once, for example on multiple interfaces
+///
+/// Devices are matched by device id, keeping the response with the strongest
+/// signal in the position the device was first seen.
+fn dedupe(devices: impl IntoIterator<Item = DiscoveredDevice>) -> Vec<DiscoveredDevice> {
+    let mut deduped: Vec<DiscoveredDevice> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for device in devices {
+        let device_id = device.data.sysinfo().device_id.clone();
+        if let Some(&index) = seen.get(&device_id) {
+            if device.data.sysinfo().rssi > deduped[index].data.sysinfo().rssi {
+                deduped[index] = device;
+            }
+        } else {
+            seen.insert(device_id, deduped.len());
+            deduped.push(device);
+        }
+    }
+    deduped
+}
+
+/// An interface that could not be searched during discovery
+#[derive(Debug)]
+pub struct InterfaceError {
+    /// Name of the local interface
+    pub interface: String,
+    /// The reason the interface could not be searched
+    pub error: Error,
+}
+
+/// Devices found during discovery along with any interfaces that failed
+#[derive(Debug)]
+pub struct Discovery {
+    /// Devices found on all interfaces
+    pub devices: Vec<DiscoveredDevice>,
+    /// Interfaces that could not be searched
+    pub errors: Vec<InterfaceError>,
+}
+
+/// Discover TPLink smart devices on the local network, reporting interfaces
+/// that could not be searched
+///
+/// A failure on one interface, for example a VPN tunnel that cannot bind a
+/// broadcast socket, does not prevent devices being found on the others.
+///
+/// # Errors
+///
+/// Will return `Err` if the local interfaces cannot be listed, there are no
+/// interfaces that can broadcast or every interface failed. In the last case
+/// the error from the first interface is returned.
+pub fn with_errors(timeout: Option<Duration>) -> Result<Discovery> {
+    with_options(&DiscoveryOptions::new().timeout(timeout))
+}
+
+/// Discover TPLink smart devices on the local network using the given options
+///
+/// # Errors
+///
+/// Will return `Err` in the same cases as [`with_errors`](with_errors).
 #[allow(clippy::needless_collect)] // needed for achieving parallelism
-pub fn with_timeout(timeout: Option<Duration>) -> Result<Vec<(SocketAddr, DeviceData)>> {
-    let request = protocol::encrypt(QUERY).unwrap();
+pub fn with_options(options: &DiscoveryOptions) -> Result<Discovery> {
+    let request = protocol::encrypt(QUERY);
     let addrs = if_addrs::get_if_addrs()?;
     thread::scope(|s| {
         let handles = addrs
             .into_iter()
-            .filter_map(can_interface_broadcast)
-            .map(|(ip, broadcast)| {
+            .filter_map(|iface| {
+                let name = iface.name.clone();
+                can_interface_broadcast(iface).map(|(ip, broadcast)| (name, ip, broadcast))
+            })
+            .map(|(name, ip, broadcast)| {
                 let request = &request;
-                s.spawn(move |_| discover_on_interface(timeout, ip, broadcast, request))
+                let interface = name.clone();
+                let handle = s.spawn(move |_| {
+                    discover_on_interface(options, ip, broadcast, request).map(|devices| {
+                        devices
+                            .into_iter()
+                            .map(|(addr, data)| DiscoveredDevice {
+                                addr,
+                                data,
+                                via_interface: Some(interface.clone()),
+                                local_ip: Some(IpAddr::V4(ip)),
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                });
+                (name, handle)
             })
             .collect::<Vec<_>>();
-        handles
-            .into_iter()
-            .filter_map(|join_handle| join_handle.join().ok().and_then(Result::ok))
-            .flat_map(|addresses| addresses)
-            .collect::<Vec<_>>()
+
+        collect_results(handles.into_iter().map(|(interface, handle)| {
+            let result = handle
+                .join()
+                .unwrap_or_else(|_| Err(DiscoveryError::Panicked.into()));
+            (interface, result)
+        }))
     })
-    .map_err(|_e| Error::Other("cannot discover devices".to_string()))
+    .map_err(|_e| Error::from(DiscoveryError::Panicked))?
+}
+
+/// Combine the results from each interface
+///
+/// Fails if there were no interfaces or every interface failed, returning the
+/// error from the first interface in the latter case.
+fn collect_results(
+    results: impl IntoIterator<Item = (String, Result<Vec<DiscoveredDevice>>)>,
+) -> Result<Discovery> {
+    let mut searched = 0;
+    let mut devices = Vec::new();
+    let mut errors = Vec::new();
+    for (interface, result) in results {
+        match result {
+            Ok(found) => {
+                searched += 1;
+                devices.extend(found);
+            }
+            Err(error) => errors.push(InterfaceError { interface, error }),
+        }
+    }
+    if searched == 0 {
+        return Err(if errors.is_empty() {
+            DiscoveryError::NoBroadcastInterfaces.into()
+        } else {
+            errors.remove(0).error
+        });
+    }
+    Ok(Discovery {
+        devices: dedupe(devices),
+        errors,
+    })
+}
+
+/// Discover TPLink smart devices on the local network, including which
+/// local interface each device was found on
+///
+/// Only IPv4 interfaces that can broadcast are searched as devices do not
+/// respond to discovery over IPv6. Devices seen on more than one interface
+/// are only returned once. Interfaces that fail are skipped, use
+/// [`with_errors`](with_errors) to find out which.
+///
+/// # Errors
+///
+/// Will return `Err` if [`with_errors`](with_errors) returns an `Err`.
+pub fn with_interface_info(timeout: Option<Duration>) -> Result<Vec<DiscoveredDevice>> {
+    Ok(with_errors(timeout)?.devices)
+}
+
+/// Discover TPLink smart devices on the local network
+///
+/// Devices seen on more than one interface are only returned once.
+///
+/// # Errors
+///
+/// Will return `Err` if [`with_interface_info`](with_interface_info) returns
+/// an `Err`.
+pub fn with_timeout(timeout: Option<Duration>) -> Result<Vec<(SocketAddr, DeviceData)>> {
+    Ok(with_interface_info(timeout)?
+        .into_iter()
+        .map(|device| (device.addr, device.data))
+        .collect())
+}
+
+/// Discover TPLink smart devices using a socket the caller has already bound
+///
+/// This is useful where creating sockets needs special permissions, to set
+/// socket options before discovery, or to discover devices from a fake device
+/// in tests. If the socket is connected the query is sent to the connected
+/// address, otherwise broadcast is turned on and it is sent to
+/// `255.255.255.255` on [`DEFAULT_PORT`](../protocol/constant.DEFAULT_PORT.html).
+/// The socket's read timeout is set to `timeout`.
+///
+/// ```no_run
+/// use std::{net::UdpSocket, time::Duration};
+/// use tplinker::discovery::discover_with_socket;
+///
+/// let socket = UdpSocket::bind("192.168.0.2:0").unwrap();
+/// for (addr, data) in discover_with_socket(&socket, Some(Duration::from_secs(3))).unwrap() {
+///     println!("{}\t{}", addr, data.sysinfo().alias);
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Will return `Err` if the socket options cannot be set.
+pub fn discover_with_socket(
+    socket: &UdpSocket,
+    timeout: Option<Duration>,
+) -> Result<Vec<(SocketAddr, DeviceData)>> {
+    let options = DiscoveryOptions::new().timeout(timeout);
+    let dest_socket_addr = match socket.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => {
+            socket.set_broadcast(true)?;
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), options.port)
+        }
+    };
+    let mut devices = Vec::new();
+    let mut seen = HashSet::new();
+    discover_on_socket(
+        &options,
+        socket,
+        dest_socket_addr,
+        &protocol::encrypt(QUERY),
+        |addr, data| {
+            if seen.insert(data.sysinfo().device_id.clone()) {
+                devices.push((addr, data));
+            }
+        },
+    )?;
+    Ok(devices)
 }
 
 /// Discover TPLink smart devices on the local network
@@ -113,3 +597,205 @@ pub fn with_timeout(timeout: Option<Duration>) -> Result<Vec<(SocketAddr, Device
 pub fn discover() -> Result<Vec<(SocketAddr, DeviceData)>> {
     with_timeout(Some(Duration::from_secs(3)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datatypes::tests::{HS100_JSON_OFF, HS110_JSON, HS300_JSON};
+    use std::io;
+
+    #[test]
+    fn discover_with_loopback_responder() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let responder_addr = responder.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut buf = [0_u8; 4096];
+            let mut queries = 0;
+            responder
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            while let Ok((size, from)) = responder.recv_from(&mut buf) {
+                assert_eq!(protocol::decrypt(&mut buf[0..size]), QUERY);
+                queries += 1;
+                let response = protocol::encrypt(HS110_JSON);
+                responder.send_to(&response[4..], from).unwrap();
+            }
+            queries
+        });
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(responder_addr).unwrap();
+
+        let devices = discover_with_socket(&socket, Some(Duration::from_millis(100))).unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].0, responder_addr);
+        assert_eq!(devices[0].1.sysinfo().alias, "Switch One");
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn discover_large_response() {
+        let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(responder.local_addr().unwrap()).unwrap();
+        let alias = "a".repeat(5000);
+        let response = HS300_JSON.replace(
+            r#""alias": "Power Strip""#,
+            &format!(r#""alias": "{}""#, alias),
+        );
+        assert!(response.len() > 4096);
+        let server = std::thread::spawn(move || {
+            let mut buf = [0_u8; 4096];
+            let (_, from) = responder.recv_from(&mut buf).unwrap();
+            responder
+                .send_to(&protocol::encrypt(&response)[4..], from)
+                .unwrap();
+        });
+
+        let devices = discover_with_socket(&socket, Some(Duration::from_millis(100))).unwrap();
+
+        server.join().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].1.sysinfo().alias, alias);
+    }
+
+    #[test]
+    fn send_queries_repeats() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let options = DiscoveryOptions::new()
+            .attempts(5)
+            .attempt_delay(Duration::from_millis(1));
+
+        send_queries(
+            &options,
+            &sender,
+            receiver.local_addr().unwrap(),
+            &protocol::encrypt(QUERY),
+        );
+
+        let mut buf = [0_u8; 4096];
+        let mut received = 0;
+        while let Ok((size, _)) = receiver.recv_from(&mut buf) {
+            assert_eq!(protocol::decrypt(&mut buf[0..size]), QUERY);
+            received += 1;
+        }
+        assert_eq!(received, 5);
+    }
+
+    #[test]
+    fn power_strip_outlets() {
+        let addr: SocketAddr = "10.0.0.1:9999".parse().unwrap();
+        let data = serde_json::from_str::<DeviceData>(HS300_JSON).unwrap();
+        let device_id = data.sysinfo().device_id.clone();
+
+        let found = outlets(addr, &data);
+
+        assert_eq!(found.len(), data.sysinfo().children.as_ref().unwrap().len());
+        assert_eq!(
+            found[0],
+            DiscoveredOutlet {
+                addr,
+                index: 1,
+                child_id: format!("{}01", device_id),
+                alias: String::from("Plug 1"),
+                is_on: true,
+                uptime: Duration::from_secs(47724),
+            }
+        );
+        assert_eq!(found[1].index, 0);
+
+        let plug = serde_json::from_str::<DeviceData>(HS110_JSON).unwrap();
+        assert!(outlets(addr, &plug).is_empty());
+    }
+
+    fn discovered(addr: &str, data: DeviceData) -> DiscoveredDevice {
+        DiscoveredDevice {
+            addr: addr.parse().unwrap(),
+            data,
+            via_interface: None,
+            local_ip: None,
+        }
+    }
+
+    #[test]
+    fn collect_results_keeps_partial_failures() {
+        let data: DeviceData = serde_json::from_str(HS100_JSON_OFF).unwrap();
+        let discovery = collect_results(vec![
+            (String::from("tun0"), Err(Error::from("bind failed"))),
+            (
+                String::from("eth0"),
+                Ok(vec![discovered("10.0.0.1:9999", data)]),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(discovery.devices.len(), 1);
+        assert_eq!(discovery.errors.len(), 1);
+        assert_eq!(discovery.errors[0].interface, "tun0");
+    }
+
+    #[test]
+    fn collect_results_no_interfaces() {
+        assert!(matches!(
+            collect_results(vec![]),
+            Err(Error::Discovery(DiscoveryError::NoBroadcastInterfaces))
+        ));
+    }
+
+    #[test]
+    fn collect_results_all_failed() {
+        let io_error = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert!(matches!(
+            collect_results(vec![(String::from("eth0"), Err(Error::IO(io_error)))]),
+            Err(Error::IO(_))
+        ));
+    }
+
+    #[test]
+    fn stream_skips_repeated_devices() {
+        let data: DeviceData = serde_json::from_str(HS100_JSON_OFF).unwrap();
+        let mut other: DeviceData = serde_json::from_str(HS110_JSON).unwrap();
+        other.system.sysinfo.device_id = String::from("other");
+
+        let (sender, receiver) = mpsc::channel();
+        for (addr, data) in [
+            ("10.0.0.1:9999", data.clone()),
+            ("10.0.1.1:9999", data),
+            ("10.0.0.2:9999", other),
+        ] {
+            sender.send((addr.parse().unwrap(), data)).unwrap();
+        }
+        drop(sender);
+        let stream = DiscoveryStream {
+            receiver,
+            seen: HashSet::new(),
+        };
+
+        let addrs = stream.map(|(addr, _)| addr.to_string()).collect::<Vec<_>>();
+        assert_eq!(addrs, vec!["10.0.0.1:9999", "10.0.0.2:9999"]);
+    }
+
+    #[test]
+    fn dedupe_by_device_id() {
+        let weak: DeviceData = serde_json::from_str(HS100_JSON_OFF).unwrap();
+        let mut strong = weak.clone();
+        strong.system.sysinfo.rssi = -30;
+        let mut other: DeviceData = serde_json::from_str(HS110_JSON).unwrap();
+        other.system.sysinfo.device_id = String::from("other");
+
+        let devices = dedupe(vec![
+            discovered("10.0.0.1:9999", weak),
+            discovered("10.0.0.2:9999", other),
+            discovered("10.0.1.1:9999", strong),
+        ]);
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].addr, "10.0.1.1:9999".parse().unwrap());
+        assert_eq!(devices[0].data.sysinfo().rssi, -30);
+        assert_eq!(devices[1].addr, "10.0.0.2:9999".parse().unwrap());
+    }
+}
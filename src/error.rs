@@ -1,6 +1,8 @@
 //! Error types
 use std::{convert::From, error, fmt, io, result};
 
+use crate::protocol::codec::FrameError;
+
 /// Error type for TPLinker
 #[derive(Debug)]
 pub enum Error {
@@ -8,10 +10,55 @@ pub enum Error {
     IO(io::Error),
     /// Wrapped errors from serde_json
     Serde(serde_json::Error),
+    /// A response from the device could not be parsed
+    ResponseParse {
+        /// The parse error
+        source: serde_json::Error,
+        /// The raw response, truncated to 1024 bytes
+        raw: String,
+    },
     /// Error decoding a section of the JSON response
     TPLink(SectionError),
+    /// Error reading the frame of a response from the device
+    Frame(FrameError),
+    /// Error discovering devices on the local network
+    Discovery(DiscoveryError),
     /// A generic error
     Other(String),
+    /// The device does not support the requested operation
+    Unsupported(String),
+    /// The device is updating its firmware so the command was not sent
+    Updating,
+}
+
+/// Longest raw response kept in [`Error::ResponseParse`](enum.Error.html#variant.ResponseParse)
+const MAX_RAW_RESPONSE: usize = 1024;
+
+impl Error {
+    /// Make a parse error keeping the raw response, truncated on a character boundary
+    pub(crate) fn response_parse(source: serde_json::Error, raw: &str) -> Self {
+        let mut end = raw.len().min(MAX_RAW_RESPONSE);
+        while !raw.is_char_boundary(end) {
+            end -= 1;
+        }
+        Error::ResponseParse {
+            source,
+            raw: raw[..end].to_string(),
+        }
+    }
+
+    /// Whether the device does not support the command
+    ///
+    /// This is the case when, for example, asking a device without an energy meter
+    /// for its energy usage, or when the library knows up front that the device
+    /// cannot do what is asked. See [`SectionError::is_unsupported`](struct.SectionError.html#method.is_unsupported).
+    pub fn is_unsupported(&self) -> bool {
+        match self {
+            Error::TPLink(err) => err.is_unsupported(),
+            Error::Unsupported(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -19,11 +66,20 @@ impl fmt::Display for Error {
         match self {
             Error::IO(_) => f.write_str("Error connecting to the device"),
             Error::Serde(_) => f.write_str("Could not parse the response received from the device"),
+            Error::ResponseParse { source, raw } => write!(
+                f,
+                "Could not parse the response received from the device ({}): {}",
+                source, raw
+            ),
             Error::TPLink(err) => f.write_str(&format!(
                 "Response data error: ({}) {}",
                 err.err_code, err.err_msg
             )),
-            Error::Other(err) => f.write_str(&err),
+            Error::Frame(err) => write!(f, "Invalid response frame: {}", err),
+            Error::Discovery(err) => write!(f, "Cannot discover devices: {}", err),
+            Error::Other(err) => f.write_str(err),
+            Error::Unsupported(err) => write!(f, "Unsupported: {}", err),
+            Error::Updating => f.write_str("The device is updating its firmware"),
         }
     }
 }
@@ -32,9 +88,15 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match self {
             Error::IO(_) => "Error connecting to the device",
-            Error::Serde(_) => "Could not parse the response received from the device",
+            Error::Serde(_) | Error::ResponseParse { .. } => {
+                "Could not parse the response received from the device"
+            }
             Error::TPLink(_) => "Response data error",
+            Error::Frame(_) => "Invalid response frame",
+            Error::Discovery(_) => "Cannot discover devices",
             Error::Other(err) => err.as_str(),
+            Error::Unsupported(err) => err.as_str(),
+            Error::Updating => "The device is updating its firmware",
         }
     }
 }
@@ -63,6 +125,18 @@ impl From<String> for Error {
     }
 }
 
+impl From<FrameError> for Error {
+    fn from(error: FrameError) -> Self {
+        Error::Frame(error)
+    }
+}
+
+impl From<DiscoveryError> for Error {
+    fn from(error: DiscoveryError) -> Self {
+        Error::Discovery(error)
+    }
+}
+
 impl From<SectionError> for Error {
     fn from(error: SectionError) -> Self {
         Error::TPLink(error)
@@ -82,6 +156,16 @@ pub struct SectionError {
     pub err_msg: String,
 }
 
+impl SectionError {
+    /// Whether the error means the module or method is not supported by the device
+    ///
+    /// Devices report this with error codes `-1` and `-2` ("module not support" and
+    /// "method not support") or `-2001` on newer firmware.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(self.err_code, -1 | -2 | -2001)
+    }
+}
+
 impl fmt::Display for SectionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(&format!("{}: {}", self.err_code, self.err_msg))
@@ -93,3 +177,51 @@ impl error::Error for SectionError {
         "TPLink section error"
     }
 }
+
+impl error::Error for FrameError {
+    fn description(&self) -> &str {
+        "Invalid response frame"
+    }
+}
+
+/// Reason devices could not be discovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryError {
+    /// There are no local IPv4 interfaces that can broadcast
+    NoBroadcastInterfaces,
+    /// A discovery thread panicked
+    Panicked,
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscoveryError::NoBroadcastInterfaces => {
+                f.write_str("no broadcast-capable interfaces found")
+            }
+            DiscoveryError::Panicked => f.write_str("discovery thread panicked"),
+        }
+    }
+}
+
+impl error::Error for DiscoveryError {
+    fn description(&self) -> &str {
+        "Cannot discover devices"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_parse_truncates_raw() {
+        let raw = "é".repeat(MAX_RAW_RESPONSE);
+        let source = serde_json::from_str::<u8>(&raw).unwrap_err();
+
+        match Error::response_parse(source, &raw) {
+            Error::ResponseParse { raw, .. } => assert_eq!(raw, "é".repeat(512)),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
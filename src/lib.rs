@@ -1,6 +1,6 @@
 //! A library to query and control `TPLink` smart devices on the local network.
 //!
-//! Supported devices include HS100, HS110, LB110, LB120, KL110, KP115.
+//! Supported devices include HS100, HS110, LB110, LB120, KL110, KP105, KP115, KP125.
 //!
 //! Inspired and influenced by [`pyHS100`](https://github.com/GadgetReactor/pyHS100) and
 //! [hs100api](https://github.com/abronan/hs100-rust-api).
@@ -19,6 +19,7 @@
 //! [`discovery::discover`](./discovery/fn.discover.html).
 //!
 //! ```no_run
+//! # #[cfg(feature = "std")] {
 //! use tplinker::{
 //!   discovery::discover,
 //!   devices::Device,
@@ -34,6 +35,7 @@
 //!     _ => {},
 //!   }
 //! }
+//! # }
 //! ```
 //!
 //! ## Direct device
@@ -42,6 +44,7 @@
 //! [`devices`](./devices/index.html).
 //!
 //! ```no_run
+//! # #[cfg(feature = "std")] {
 //! use tplinker::{
 //!   devices::LB110,
 //!   capabilities::{Switch, Dimmer},
@@ -54,6 +57,7 @@
 //!     device.set_brightness(brightness + 20).unwrap();
 //!   }
 //! }
+//! # }
 //! ```
 //!
 //! ## Capabilities
@@ -61,18 +65,42 @@
 //! In order to do things with devices you must bring in the relevant capability
 //! traits from [`capabilities`](./capabilities/index.html).
 
+//!
+//! ## Features
+//!
+//! The default `std` feature builds everything that talks to devices. Without
+//! it the crate is `no_std` and only has the
+//! [`protocol::codec`](./protocol/codec/index.html) module, for implementing
+//! a transport elsewhere.
+
 #![deny(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+#[cfg(feature = "std")]
 extern crate byteorder;
 
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate serde_derive;
 
+#[cfg(feature = "std")]
 pub mod capabilities;
+#[cfg(feature = "std")]
+mod colour;
+#[cfg(feature = "std")]
 pub mod datatypes;
+#[cfg(feature = "std")]
 pub mod devices;
+#[cfg(feature = "std")]
 pub mod discovery;
+#[cfg(feature = "std")]
 pub mod error;
-mod protocol;
+#[cfg(all(feature = "std", any(test, feature = "metrics")))]
+pub mod metrics;
+pub mod protocol;
+#[cfg(all(feature = "std", any(test, feature = "testing")))]
+pub mod testing;
 
+#[cfg(feature = "std")]
 pub use discovery::discover;
@@ -4,30 +4,39 @@ use clap::{App, AppSettings, Arg, SubCommand};
 use serde_json::{json, to_string as stringify, Value};
 
 use tplinker::{
-    capabilities::{DeviceActions, MultiSwitch, Switch},
+    capabilities::{DeviceActions, MultiSwitch},
     datatypes::{DeviceData, SysInfo},
-    devices::{Device, RawDevice, HS100, HS103, HS105, HS110, HS300, KL110, LB110, LB120},
+    devices::{resolve_address, status_many, Device, RawDevice},
     error::Result as TpResult,
 };
 
 fn command_discover(timeout: Option<Duration>, format: Format) -> Vec<Value> {
-    tplinker::discovery::with_timeout(timeout)
-        .unwrap()
+    let discovery = match tplinker::discovery::with_errors(timeout) {
+        Ok(discovery) => discovery,
+        Err(err) => {
+            eprintln!("{}", err);
+            return vec![];
+        }
+    };
+    for err in discovery.errors {
+        eprintln!("While discovering on {}: {}", err.interface, err.error);
+    }
+    discovery
+        .devices
         .into_iter()
-        .map(|(addr, data)| {
-            let device = Device::from_data(addr, &data);
-            format.discover(addr, device, data)
+        .map(|found| {
+            let device = Device::from_data(found.addr, &found.data);
+            format.discover(found.addr, device, found.data)
         })
         .collect()
 }
 
 fn command_status(addresses: Vec<SocketAddr>, format: Format) -> Vec<Value> {
-    use rayon::prelude::*;
-    addresses
-        .into_par_iter()
-        .filter_map(|addr| {
-            device_from_addr(addr)
-                .map(|(addr, dev, info)| format.status(addr, dev, &info))
+    status_many(&addresses)
+        .into_iter()
+        .filter_map(|(addr, result)| {
+            result
+                .map(|(dev, info)| format.status(addr, dev, &info))
                 .map_err(|err| eprintln!("While querying {}: {}", addr, err))
                 .ok()
         })
@@ -101,17 +110,10 @@ fn command_switch_toggle(
                 Value::Bool(false)
             } else {
                 match &dev {
-                    Device::HS100(s) => toggle_switch(s, state),
-                    Device::HS103(s) => toggle_switch(s, state),
-                    Device::HS105(s) => toggle_switch(s, state),
-                    Device::HS110(s) => toggle_switch(s, state),
                     Device::HS300(s) if index.is_some() => {
-                        toggle_multiswitch(s, state, index.unwrap())
+                        toggle_multiswitch(s, state, index.unwrap(), actual)
                     }
-                    Device::LB110(s) => toggle_switch(s, state),
-                    Device::LB120(s) => toggle_switch(s, state),
-                    Device::KL110(s) => toggle_switch(s, state),
-                    _ => panic!("not a switchable device: {}", addr),
+                    dev => toggle_switch(dev, state, actual),
                 }
                 .map(|_| Value::Bool(true))
                 .unwrap_or_else(|err| {
@@ -135,46 +137,7 @@ fn command_switch_toggle(
 }
 
 fn device_from_addr(addr: SocketAddr) -> TpResult<(SocketAddr, Device, SysInfo)> {
-    let raw = RawDevice::from_addr(addr);
-    let info = raw.sysinfo()?;
-
-    // Re-interpret as correct model
-    let (dev, info) = if info.model.starts_with("HS100") {
-        let dev = HS100::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::HS100(dev), info)
-    } else if info.model.starts_with("HS103") {
-        let dev = HS103::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::HS103(dev), info)
-    } else if info.model.starts_with("HS105") {
-        let dev = HS105::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::HS105(dev), info)
-    } else if info.model.starts_with("HS110") {
-        let dev = HS110::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::HS110(dev), info)
-    } else if info.model.starts_with("HS300") {
-        let dev = HS300::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::HS300(dev), info)
-    } else if info.model.starts_with("LB110") {
-        let dev = LB110::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::LB110(dev), info)
-    } else if info.model.starts_with("LB120") {
-        let dev = LB120::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::LB120(dev), info)
-    } else if info.model.starts_with("KL110") {
-        let dev = KL110::from_raw(raw);
-        let info = dev.sysinfo()?;
-        (Device::KL110(dev), info)
-    } else {
-        (Device::Unknown(raw), info)
-    };
-
+    let (dev, info) = Device::identify(addr)?;
     Ok((addr, dev, info))
 }
 
@@ -185,32 +148,30 @@ fn pad(value: &str, padding: usize) -> String {
 
 fn device_is_on(device: &Device, index: Option<usize>) -> Option<bool> {
     match device {
-        Device::HS100(device) => device.is_on().ok(),
-        Device::HS103(device) => device.is_on().ok(),
-        Device::HS105(device) => device.is_on().ok(),
-        Device::HS110(device) => device.is_on().ok(),
-        Device::HS300(device) if index.is_some() => device.is_on(index.unwrap()).ok(),
-        Device::LB110(device) => device.is_on().ok(),
-        Device::LB120(device) => device.is_on().ok(),
-        Device::KL110(device) => device.is_on().ok(),
-        _ => None,
+        Device::HS300(device) => index.and_then(|index| device.is_on(index).ok()),
+        device => device.is_on().ok(),
     }
 }
 
-fn toggle_switch<S: Switch>(switch: &S, state: &str) -> TpResult<bool> {
+fn toggle_switch(device: &Device, state: &str, is_on: bool) -> TpResult<bool> {
     match state {
-        "on" => switch.switch_on().and(Ok(true)),
-        "off" => switch.switch_off().and(Ok(false)),
-        "toggle" => switch.toggle(),
+        "on" => device.switch_on().and(Ok(true)),
+        "off" => device.switch_off().and(Ok(false)),
+        "toggle" => device.toggle_from(is_on),
         _ => unreachable!(),
     }
 }
 
-fn toggle_multiswitch<S: MultiSwitch>(switch: &S, state: &str, index: usize) -> TpResult<bool> {
+fn toggle_multiswitch<S: MultiSwitch>(
+    switch: &S,
+    state: &str,
+    index: usize,
+    is_on: bool,
+) -> TpResult<bool> {
     match state {
         "on" => switch.switch_on(index).and(Ok(true)),
         "off" => switch.switch_off(index).and(Ok(false)),
-        "toggle" => switch.toggle(index),
+        "toggle" => switch.toggle_from(index, is_on),
         _ => unreachable!(),
     }
 }
@@ -263,7 +224,7 @@ impl Format {
                             {
                                 let order_next = fields.len();
                                 let k = key.as_str().unwrap().to_string();
-                                let h = human_stringify(&value);
+                                let h = human_stringify(value);
                                 let hlen = h.len().max(k.len());
                                 proc.insert(k.clone(), h);
                                 fields
@@ -281,7 +242,7 @@ impl Format {
                     }
 
                     let mut fields: Vec<(String, (usize, usize))> = fields.into_iter().collect();
-                    fields.sort_unstable_by(|(_, (a, _)), (_, (b, _))| a.cmp(b));
+                    fields.sort_unstable_by_key(|(_, (order, _))| *order);
                     let fields: Vec<(String, usize)> = fields
                         .into_iter()
                         .map(|(name, (_, width))| (name, width))
@@ -341,7 +302,7 @@ impl Format {
                 ["Alias", sysinfo.alias],
                 ["Product", sysinfo.dev_name],
                 ["Model", sysinfo.model],
-                ["Signal", format!("{} dB", sysinfo.rssi)],
+                ["Signal", sysinfo.signal().to_string()],
                 ["On?", device_is_on(&device, None)],
             ]),
             Format::Long => {
@@ -358,7 +319,7 @@ impl Format {
                     ["Type", sysinfo.hw_type],
                     ["Model", sysinfo.model],
                     ["Version", sysinfo.sw_ver],
-                    ["Signal", format!("{} dB", sysinfo.rssi)],
+                    ["Signal", sysinfo.signal().to_string()],
                     ["Latitude", lat],
                     ["Longitude", lon],
                     ["Mode", sysinfo.active_mode],
@@ -431,6 +392,8 @@ impl Format {
             Device::LB120(_) => "LB120",
             Device::KL110(_) => "KL110",
             Device::KP115(_) => "KP115",
+            Device::KP105(_) => "KP105",
+            Device::KP125(_) => "KP125",
             Device::Unknown(_) => "unknown",
         }
     }
@@ -518,12 +481,7 @@ fn main() {
     }
 
     fn parse_address(addr: &str) -> SocketAddr {
-        addr.parse()
-            .map_err(|_| ())
-            .or_else(|_| -> Result<_, ()> {
-                Ok(SocketAddr::new(addr.parse().map_err(|_| ())?, 9999))
-            })
-            .unwrap_or_else(|_| panic!("not a valid address: {}", addr))
+        resolve_address(addr).unwrap_or_else(|err| panic!("{}", err))
     }
 
     fn parse_addresses(matches: &clap::ArgMatches) -> Vec<SocketAddr> {
@@ -543,9 +501,9 @@ fn main() {
 
             command_discover(timeout, format)
         }
-        ("status", Some(matches)) => command_status(parse_addresses(&matches), format),
+        ("status", Some(matches)) => command_status(parse_addresses(matches), format),
         ("reboot", Some(matches)) => command_reboot(
-            parse_addresses(&matches),
+            parse_addresses(matches),
             parse_seconds(matches.value_of("delay").unwrap(), 1),
             format,
         ),
@@ -0,0 +1,191 @@
+//! Export energy meter readings as OpenMetrics text for Prometheus
+//!
+//! Enable the `metrics` feature to use this module.
+//!
+//! ```no_run
+//! use tplinker::{devices::HS110, metrics::{encode, EmeterSample}};
+//!
+//! let device = HS110::new("192.168.0.99:9999").unwrap();
+//! let sample = EmeterSample::read(&device).unwrap();
+//! print!("{}", encode(&[sample]));
+//! ```
+use std::fmt::Write;
+
+use crate::{
+    capabilities::Emeter,
+    datatypes::{EnergyReading, SysInfo},
+    error::Result,
+};
+
+/// Energy meter readings from one device, in SI units
+///
+/// Devices report readings in either base or milli units depending on their
+/// hardware version, they are converted by
+/// [`EnergyReading`](../datatypes/trait.EnergyReading.html). Readings a device
+/// does not report, such as voltage on bulbs, are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmeterSample {
+    /// Device alias, used as a label
+    pub alias: String,
+    /// Device model, used as a label
+    pub model: String,
+    /// Power in watts
+    pub power_watts: Option<f64>,
+    /// Voltage in volts
+    pub voltage_volts: Option<f64>,
+    /// Current in amperes
+    pub current_amperes: Option<f64>,
+    /// Total energy used in kilowatt hours
+    pub energy_kilowatt_hours: Option<f64>,
+}
+
+impl EmeterSample {
+    /// Read the system information and realtime energy usage of a device
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if either request to the device fails.
+    pub fn read<D: Emeter>(device: &D) -> Result<Self> {
+        let sysinfo = device.sysinfo()?;
+        let reading = device.get_energy_reading()?;
+        Ok(Self::from_reading(&sysinfo, &reading))
+    }
+
+    /// Make a sample from system information and an energy reading
+    pub fn from_reading<R: EnergyReading>(sysinfo: &SysInfo, reading: &R) -> Self {
+        Self {
+            alias: sysinfo.alias.clone(),
+            model: sysinfo.model.clone(),
+            power_watts: Some(reading.power_watts()),
+            voltage_volts: reading.voltage_volts(),
+            current_amperes: reading.current_amperes(),
+            energy_kilowatt_hours: reading.total_kilowatt_hours(),
+        }
+    }
+}
+
+/// Encode samples from any number of devices as an OpenMetrics exposition
+pub fn encode(samples: &[EmeterSample]) -> String {
+    let mut out = String::new();
+    family(
+        &mut out,
+        samples,
+        "tplink_power_watts",
+        "gauge",
+        "Power usage",
+        |s| s.power_watts,
+    );
+    family(
+        &mut out,
+        samples,
+        "tplink_voltage_volts",
+        "gauge",
+        "Mains voltage",
+        |s| s.voltage_volts,
+    );
+    family(
+        &mut out,
+        samples,
+        "tplink_current_amperes",
+        "gauge",
+        "Current drawn",
+        |s| s.current_amperes,
+    );
+    family(
+        &mut out,
+        samples,
+        "tplink_energy_kilowatt_hours",
+        "counter",
+        "Total energy used",
+        |s| s.energy_kilowatt_hours,
+    );
+    out.push_str("# EOF\n");
+    out
+}
+
+fn family<F>(
+    out: &mut String,
+    samples: &[EmeterSample],
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: F,
+) where
+    F: Fn(&EmeterSample) -> Option<f64>,
+{
+    let values = samples
+        .iter()
+        .filter_map(|sample| value(sample).map(|value| (sample, value)))
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return;
+    }
+    let suffix = if kind == "counter" { "_total" } else { "" };
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    for (sample, value) in values {
+        let _ = writeln!(
+            out,
+            "{}{}{{alias=\"{}\",model=\"{}\"}} {}",
+            name,
+            suffix,
+            escape(&sample.alias),
+            escape(&sample.model),
+            value
+        );
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    use crate::datatypes::{
+        tests::{HS110_JSON, KP115_JSON},
+        DeviceData, PlugEmeterRealtime,
+    };
+
+    fn sample(json: &str) -> EmeterSample {
+        let data: Value = serde_json::from_str(json).unwrap();
+        let device_data: DeviceData = serde_json::from_str(json).unwrap();
+        let reading: PlugEmeterRealtime =
+            serde_json::from_value(data["emeter"]["get_realtime"].clone()).unwrap();
+        EmeterSample::from_reading(device_data.sysinfo(), &reading)
+    }
+
+    #[test]
+    fn converts_milli_units() {
+        let sample = sample(KP115_JSON);
+        assert_eq!(sample.model, "KP115(UK)");
+        assert_eq!(sample.power_watts, Some(1.742));
+        assert_eq!(sample.voltage_volts, Some(235.437));
+        assert_eq!(sample.current_amperes, Some(0.019));
+        assert_eq!(sample.energy_kilowatt_hours, Some(9.064));
+    }
+
+    #[test]
+    fn encode_samples() {
+        let mut sample = sample(HS110_JSON);
+        sample.alias = String::from("Kettle \"big\"");
+        sample.voltage_volts = None;
+        sample.current_amperes = None;
+        sample.energy_kilowatt_hours = None;
+        sample.power_watts = Some(10.5);
+
+        assert_eq!(
+            encode(&[sample]),
+            "# TYPE tplink_power_watts gauge\n\
+             # HELP tplink_power_watts Power usage\n\
+             tplink_power_watts{alias=\"Kettle \\\"big\\\"\",model=\"HS110(UK)\"} 10.5\n\
+             # EOF\n"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! The XOR autokey cipher used by `TPLink` smart devices
+//!
+//! Only `core` and `alloc` types are used here so the codec can be reused with
+//! any transport.
+//!
+//! see: <https://www.softscheck.com/en/reverse-engineering-tp-link-hs110/>
+//!
+//! ```
+//! use tplinker::protocol::codec::{decrypt_owned, encrypt};
+//!
+//! let mut frame = encrypt(r#"{"system":{"get_sysinfo":null}}"#);
+//! let payload = frame.split_off(4);
+//! assert_eq!(decrypt_owned(payload), r#"{"system":{"get_sysinfo":null}}"#);
+//! ```
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+const INITIAL_KEY: u8 = 0xAB;
+
+/// Error reading the length prefixed frame of a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The response was too short to contain the 4 byte length prefix
+    TooShort(usize),
+    /// The payload length did not match the length prefix
+    LengthMismatch {
+        /// Length given in the prefix
+        expected: usize,
+        /// Length of the payload received
+        actual: usize,
+    },
+    /// The response is longer than the transport accepts
+    TooLong {
+        /// Length of the response
+        length: usize,
+        /// Longest response accepted
+        max: usize,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::TooShort(len) => write!(
+                f,
+                "response of {} bytes is too short for the length prefix",
+                len
+            ),
+            FrameError::LengthMismatch { expected, actual } => write!(
+                f,
+                "expected a payload of {} bytes but received {} bytes",
+                expected, actual
+            ),
+            FrameError::TooLong { length, max } => write!(
+                f,
+                "response of {} bytes is longer than the maximum of {} bytes",
+                length, max
+            ),
+        }
+    }
+}
+
+/// Encrypt a message to send to a device
+///
+/// The encrypted payload is prefixed with its length as a big endian `u32`, as
+/// expected by devices over TCP.
+pub fn encrypt(plain: &str) -> Vec<u8> {
+    let mut cipher = Vec::with_capacity(plain.len() + 4);
+    #[allow(clippy::cast_possible_truncation)]
+    cipher.extend_from_slice(&(plain.len() as u32).to_be_bytes());
+
+    let mut key = INITIAL_KEY;
+    for byte in plain.bytes() {
+        key ^= byte;
+        cipher.push(key);
+    }
+
+    cipher
+}
+
+/// Decrypt a payload received from a device, in place
+///
+/// The payload must not include the length prefix. Invalid UTF-8 is replaced
+/// with `U+FFFD`.
+pub fn decrypt(cipher: &mut [u8]) -> String {
+    let mut key = INITIAL_KEY;
+    for item in cipher.iter_mut() {
+        let next = *item;
+        *item ^= key;
+        key = next;
+    }
+
+    String::from_utf8_lossy(cipher).into_owned()
+}
+
+/// Decrypt a length prefixed frame received from a device, in place
+///
+/// Unlike [`decrypt`](fn.decrypt.html) this checks the big endian length
+/// prefix matches the length of the payload, so a truncated response is
+/// reported rather than decrypted.
+///
+/// # Errors
+///
+/// Will return `Err` if the frame is shorter than the prefix or the payload
+/// length does not match the prefix.
+pub fn decrypt_checked(frame: &mut [u8]) -> Result<String, FrameError> {
+    if frame.len() < 4 {
+        return Err(FrameError::TooShort(frame.len()));
+    }
+    let (prefix, payload) = frame.split_at_mut(4);
+    let expected = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+    if payload.len() != expected {
+        return Err(FrameError::LengthMismatch {
+            expected,
+            actual: payload.len(),
+        });
+    }
+    Ok(decrypt(payload))
+}
+
+/// Decrypt a payload received from a device, taking ownership of the buffer
+///
+/// See [`decrypt`](fn.decrypt.html).
+pub fn decrypt_owned(mut cipher: Vec<u8>) -> String {
+    decrypt(&mut cipher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn encrypt_known_value() {
+        assert_eq!(encrypt("{}"), vec![0, 0, 0, 2, 0xD0, 0xAD]);
+    }
+
+    #[test]
+    fn decrypt_known_value() {
+        assert_eq!(decrypt(&mut [0xD0, 0xAD]), "{}");
+        assert_eq!(decrypt_owned(vec![0xD0, 0xAD]), "{}");
+    }
+
+    #[test]
+    fn decrypt_checked_frame() {
+        assert_eq!(decrypt_checked(&mut encrypt("{}")), Ok(String::from("{}")));
+    }
+
+    #[test]
+    fn decrypt_checked_too_short() {
+        assert_eq!(
+            decrypt_checked(&mut [0, 0, 2]),
+            Err(FrameError::TooShort(3))
+        );
+    }
+
+    #[test]
+    fn decrypt_checked_truncated() {
+        let mut frame = encrypt("{\"system\":{}}");
+        frame.truncate(8);
+        assert_eq!(
+            decrypt_checked(&mut frame),
+            Err(FrameError::LengthMismatch {
+                expected: 13,
+                actual: 4
+            })
+        );
+    }
+}
@@ -0,0 +1,23 @@
+//! Transports used to send commands to devices
+//!
+//! The [`codec`](codec/index.html) module contains the encryption used by all
+//! transports and can be used on its own to implement another transport. It is
+//! the only part of the module built without the default `std` feature.
+pub mod codec;
+
+pub use codec::{decrypt, decrypt_checked, decrypt_owned, encrypt, FrameError};
+
+/// The port devices listen on for commands and discovery by default
+pub const DEFAULT_PORT: u16 = 9999;
+
+/// The longest response accepted by default, 1MiB
+///
+/// Responses are length prefixed so without a limit a faulty or malicious
+/// device could make a transport allocate as much memory as it claims to send.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 1 << 20;
+
+#[cfg(feature = "std")]
+mod transport;
+
+#[cfg(feature = "std")]
+pub use transport::{DefaultProtocol, PooledProtocol, Protocol, UdpProtocol};
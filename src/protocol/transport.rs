@@ -0,0 +1,639 @@
+//! TCP and UDP transports, only built with the `std` feature
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use log::debug;
+use socket2::{SockRef, TcpKeepalive};
+
+use super::{
+    codec::{decrypt, decrypt_checked, encrypt, FrameError},
+    DEFAULT_MAX_RESPONSE_SIZE, DEFAULT_PORT,
+};
+use crate::error::Error;
+
+/// A transport for sending commands to a device
+pub trait Protocol: Send {
+    /// Send a JSON command to the device at `ip` and return the JSON response
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there is a problem communicating with the device.
+    fn send(&self, ip: SocketAddr, msg: &str) -> Result<String, Error>;
+
+    /// Send a JSON command, waiting at most `timeout` to connect, send and receive
+    ///
+    /// Transports that cannot change their timeout for a single command use
+    /// their own timeout, which is what the default does.
+    fn send_with_timeout(
+        &self,
+        ip: SocketAddr,
+        msg: &str,
+        _timeout: Duration,
+    ) -> Result<String, Error> {
+        self.send(ip, msg)
+    }
+
+    /// The port used when an address is given without one
+    ///
+    /// This is [`DEFAULT_PORT`](constant.DEFAULT_PORT.html) unless the transport
+    /// talks to devices on a different port.
+    fn default_port(&self) -> u16 {
+        DEFAULT_PORT
+    }
+}
+
+/// The default transport, sending commands over TCP on port 9999
+///
+/// Each command waits up to 5 seconds, see
+/// [`Protocol::send_with_timeout`](trait.Protocol.html#method.send_with_timeout)
+/// to wait for a different time. Responses longer than
+/// [`DEFAULT_MAX_RESPONSE_SIZE`](constant.DEFAULT_MAX_RESPONSE_SIZE.html) are
+/// rejected, use [`with_max_response_size`](#method.with_max_response_size)
+/// to accept a different size.
+#[derive(Clone, Debug)]
+pub struct DefaultProtocol {
+    max_response_size: usize,
+}
+
+impl DefaultProtocol {
+    /// Reject responses longer than `max_response_size` bytes, the default is
+    /// [`DEFAULT_MAX_RESPONSE_SIZE`](constant.DEFAULT_MAX_RESPONSE_SIZE.html)
+    ///
+    /// ```no_run
+    /// use tplinker::{devices::{RawDevice, HS300}, protocol::DefaultProtocol};
+    ///
+    /// let protocol = DefaultProtocol::default().with_max_response_size(64 * 1024);
+    /// let strip = HS300::from_raw(RawDevice::with_protocol("192.168.0.99:9999".parse().unwrap(), protocol));
+    /// ```
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+}
+
+impl Default for DefaultProtocol {
+    fn default() -> Self {
+        Self {
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+        }
+    }
+}
+
+impl Protocol for DefaultProtocol {
+    fn send(&self, ip: SocketAddr, msg: &str) -> Result<String, Error> {
+        self.send_with_timeout(ip, msg, Duration::new(5, 0))
+    }
+
+    fn send_with_timeout(
+        &self,
+        ip: SocketAddr,
+        msg: &str,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        debug!("Sending to {}: {}", ip, msg);
+        let payload = encrypt(msg);
+        let mut stream = TcpStream::connect_timeout(&ip, timeout)?;
+
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(&payload)?;
+
+        let mut resp = vec![];
+        let mut buffer: [u8; 4096] = [0; 4096];
+        let mut length: Option<u32> = None;
+
+        loop {
+            let read = match stream.read(&mut buffer) {
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if length.is_none() {
+                length = Some(BigEndian::read_u32(&buffer[0..4]));
+            }
+            resp.extend_from_slice(&buffer[0..read]);
+            // Devices may not send a length prefix so also check what has arrived
+            let size = (length.unwrap() as usize).max(resp.len().saturating_sub(4));
+            if size > self.max_response_size {
+                return Err(FrameError::TooLong {
+                    length: size,
+                    max: self.max_response_size,
+                }
+                .into());
+            }
+            let lval = length.unwrap() as usize;
+            if lval > 0 && resp.len().saturating_sub(4) >= lval || read == 0 {
+                break;
+            }
+        }
+        let resp = decrypt_checked(&mut resp)?;
+        debug!("Response from {}: {}", ip, resp);
+        Ok(resp)
+    }
+}
+
+/// A transport sending commands over TCP, keeping connections open for reuse
+///
+/// Clones share the same pool of connections, keyed by device address, so a
+/// single pool can be shared by many devices and threads. A connection is only
+/// used by one command at a time; concurrent commands to the same device open
+/// extra connections. If a pooled connection has been closed by the device the
+/// command is retried once on a new connection. Other errors, such as a
+/// timeout waiting for the response, are returned without retrying as the
+/// device may already have run the command.
+///
+/// ```no_run
+/// use std::thread;
+/// use tplinker::{capabilities::Switch, devices::{RawDevice, HS100}, protocol::PooledProtocol};
+///
+/// let pool = PooledProtocol::default();
+/// let device = HS100::from_raw(RawDevice::with_protocol("192.168.0.99:9999".parse().unwrap(), pool.clone()));
+/// thread::scope(|s| {
+///     s.spawn(|| device.switch_on().unwrap());
+///     s.spawn(|| device.is_on().unwrap());
+/// });
+/// ```
+///
+/// For steady polling, such as monitoring energy usage every few seconds, turn
+/// on TCP keepalive with [`with_keepalive`](#method.with_keepalive) so idle
+/// connections stay open between polls and dead ones are noticed.
+#[derive(Clone, Debug)]
+pub struct PooledProtocol {
+    timeout: Duration,
+    keepalive: Option<Duration>,
+    max_response_size: usize,
+    connections: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>,
+}
+
+impl PooledProtocol {
+    /// Make a pooled transport waiting up to `timeout` for each response
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            keepalive: None,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Send TCP keepalive probes after connections have been idle for `idle`
+    ///
+    /// ```no_run
+    /// use std::{thread, time::Duration};
+    /// use tplinker::{capabilities::Emeter, devices::{RawDevice, HS110}, protocol::PooledProtocol};
+    ///
+    /// let protocol = PooledProtocol::default().with_keepalive(Duration::from_secs(10));
+    /// let plug = HS110::from_raw(RawDevice::with_protocol("192.168.0.99:9999".parse().unwrap(), protocol));
+    /// loop {
+    ///     println!("{:?}", plug.get_emeter_realtime());
+    ///     thread::sleep(Duration::from_secs(5));
+    /// }
+    /// ```
+    pub fn with_keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Reject responses longer than `max_response_size` bytes, the default is
+    /// [`DEFAULT_MAX_RESPONSE_SIZE`](constant.DEFAULT_MAX_RESPONSE_SIZE.html)
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    fn connect(&self, ip: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&ip, timeout)?;
+        if let Some(idle) = self.keepalive {
+            SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+        Ok(stream)
+    }
+
+    fn exchange(
+        &self,
+        stream: &mut TcpStream,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ExchangeError> {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(payload).map_err(ExchangeError::Closed)?;
+        let mut length = [0_u8; 4];
+        // A closed connection ends or is reset before any of the response
+        match stream.read(&mut length[..1]) {
+            Ok(0) => return Err(ExchangeError::Closed(io::ErrorKind::UnexpectedEof.into())),
+            Ok(_) => {}
+            Err(err) if is_closed(&err) => return Err(ExchangeError::Closed(err)),
+            Err(err) => return Err(err.into()),
+        }
+        stream.read_exact(&mut length[1..])?;
+        let length = BigEndian::read_u32(&length) as usize;
+        if length > self.max_response_size {
+            return Err(FrameError::TooLong {
+                length,
+                max: self.max_response_size,
+            }
+            .into());
+        }
+        let mut resp = vec![0_u8; 4 + length];
+        BigEndian::write_u32(&mut resp[..4], length as u32);
+        stream.read_exact(&mut resp[4..])?;
+        Ok(resp)
+    }
+}
+
+/// Why sending a command on a connection failed
+enum ExchangeError {
+    /// The connection was closed before the device could have responded, so
+    /// the command can be sent again on a new connection
+    Closed(io::Error),
+    /// Anything else, where the device may have run the command
+    Failed(Error),
+}
+
+impl From<io::Error> for ExchangeError {
+    fn from(error: io::Error) -> Self {
+        ExchangeError::Failed(error.into())
+    }
+}
+
+impl From<FrameError> for ExchangeError {
+    fn from(error: FrameError) -> Self {
+        ExchangeError::Failed(error.into())
+    }
+}
+
+impl From<ExchangeError> for Error {
+    fn from(error: ExchangeError) -> Self {
+        match error {
+            ExchangeError::Closed(err) => err.into(),
+            ExchangeError::Failed(err) => err,
+        }
+    }
+}
+
+fn is_closed(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+impl Default for PooledProtocol {
+    fn default() -> Self {
+        Self::with_timeout(Duration::new(5, 0))
+    }
+}
+
+impl Protocol for PooledProtocol {
+    fn send(&self, ip: SocketAddr, msg: &str) -> Result<String, Error> {
+        self.send_with_timeout(ip, msg, self.timeout)
+    }
+
+    fn send_with_timeout(
+        &self,
+        ip: SocketAddr,
+        msg: &str,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        debug!("Sending to {}: {}", ip, msg);
+        let payload = encrypt(msg);
+        let pooled = self.connections.lock().unwrap().remove(&ip);
+        let (stream, mut resp) = match pooled.map(|mut stream| {
+            let resp = self.exchange(&mut stream, &payload, timeout);
+            (stream, resp)
+        }) {
+            Some((stream, Ok(resp))) => (stream, resp),
+            // Sending again could run the command twice
+            Some((_, Err(ExchangeError::Failed(err)))) => return Err(err),
+            // The device closed the pooled connection so try a new one
+            pooled => {
+                if let Some((_, Err(ExchangeError::Closed(err)))) = pooled {
+                    debug!("Reconnecting to {}: {}", ip, err);
+                }
+                let mut stream = self.connect(ip, timeout)?;
+                let resp = self.exchange(&mut stream, &payload, timeout)?;
+                (stream, resp)
+            }
+        };
+        self.connections.lock().unwrap().insert(ip, stream);
+        let resp = decrypt_checked(&mut resp)?;
+        debug!("Response from {}: {}", ip, resp);
+        Ok(resp)
+    }
+}
+
+/// A transport sending commands over UDP on port 9999
+///
+/// This avoids the cost of setting up a TCP connection which makes it faster
+/// for simple commands such as switching a relay. The whole response must fit
+/// in a single datagram so large responses, such as emeter statistics, may be
+/// lost or truncated. Use [`DefaultProtocol`](struct.DefaultProtocol.html) for
+/// those.
+#[derive(Clone, Debug)]
+pub struct UdpProtocol {
+    timeout: Duration,
+}
+
+impl UdpProtocol {
+    /// Make a UDP transport waiting up to `timeout` for each response
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl Default for UdpProtocol {
+    fn default() -> Self {
+        Self::with_timeout(Duration::new(5, 0))
+    }
+}
+
+impl Protocol for UdpProtocol {
+    fn send(&self, ip: SocketAddr, msg: &str) -> Result<String, Error> {
+        self.send_with_timeout(ip, msg, self.timeout)
+    }
+
+    fn send_with_timeout(
+        &self,
+        ip: SocketAddr,
+        msg: &str,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        debug!("Sending to {} over UDP: {}", ip, msg);
+        let payload = encrypt(msg);
+        let local_ip = match ip {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0))?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.connect(ip)?;
+        socket.send(&payload[4..])?;
+
+        let mut buffer = vec![0_u8; 65_536];
+        let read = socket.recv(&mut buffer)?;
+        let resp = decrypt(&mut buffer[0..read]);
+        debug!("Response from {} over UDP: {}", ip, resp);
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{TcpListener, UdpSocket},
+        sync::mpsc::channel,
+        thread,
+    };
+
+    #[test]
+    fn encrypt_decrypt() {
+        let json = "{\"system\":{\"get_sysinfo\":{}}}";
+
+        let mut data = encrypt(json);
+        let resp = decrypt(&mut data.split_off(4));
+
+        assert_eq!(json, resp);
+    }
+
+    #[test]
+    fn protocol_send() {
+        // arrange
+        let protocol = DefaultProtocol::default();
+        let msg = "{\"system\":{\"get_sysinfo\":{}}}";
+        let resp = "great response";
+
+        let (sender, ready) = channel();
+        thread::spawn(move || {
+            let listener: TcpListener;
+            // Bind to lowest available port
+            let mut port = 5818;
+            loop {
+                match TcpListener::bind(format!("127.0.0.1:{}", port)) {
+                    Ok(ok) => {
+                        listener = ok;
+                        break;
+                    }
+                    Err(_) => {
+                        port += 1;
+                    }
+                }
+            }
+
+            sender.send(port).unwrap();
+            if let Ok((mut socket, _)) = listener.accept() {
+                socket.write_all(&encrypt(resp)).unwrap();
+            }
+        });
+        let port = ready.recv().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+
+        // act
+        let result = protocol.send(addr, msg).unwrap();
+
+        // assert
+        assert_eq!(result, resp.to_string());
+    }
+
+    #[test]
+    fn protocol_send_with_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // Accept but never reply
+            let (socket, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(500));
+            drop(socket);
+        });
+
+        let result =
+            DefaultProtocol::default().send_with_timeout(addr, "{}", Duration::from_millis(50));
+
+        match result {
+            Err(Error::IO(err)) => assert!(matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )),
+            other => panic!("unexpected result {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn pooled_protocol_reuses_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut accepted = 0;
+            for mut socket in listener.incoming().take(2).flatten() {
+                accepted += 1;
+                let mut length = [0_u8; 4];
+                while socket.read_exact(&mut length).is_ok() {
+                    let mut msg = vec![0_u8; BigEndian::read_u32(&length) as usize];
+                    socket.read_exact(&mut msg).unwrap();
+                    let reply = decrypt(&mut msg).to_uppercase();
+                    socket.write_all(&encrypt(&reply)).unwrap();
+                    if reply == "CLOSE" {
+                        break;
+                    }
+                }
+            }
+            accepted
+        });
+        let protocol = PooledProtocol::default();
+
+        assert_eq!(protocol.send(addr, "one").unwrap(), "ONE");
+        assert_eq!(protocol.clone().send(addr, "two").unwrap(), "TWO");
+        assert_eq!(protocol.send(addr, "close").unwrap(), "CLOSE");
+        assert_eq!(protocol.send(addr, "three").unwrap(), "THREE");
+        drop(protocol);
+
+        assert_eq!(server.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn pooled_protocol_does_not_resend_after_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, received) = channel();
+        thread::spawn(move || {
+            for mut socket in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    let mut length = [0_u8; 4];
+                    while socket.read_exact(&mut length).is_ok() {
+                        let mut msg = vec![0_u8; BigEndian::read_u32(&length) as usize];
+                        socket.read_exact(&mut msg).unwrap();
+                        let msg = decrypt(&mut msg);
+                        sender.send(msg.clone()).unwrap();
+                        if msg == "stall" {
+                            thread::sleep(Duration::from_millis(500));
+                            break;
+                        }
+                        socket.write_all(&encrypt(&msg)).unwrap();
+                    }
+                });
+            }
+        });
+        let protocol = PooledProtocol::with_timeout(Duration::from_millis(100));
+
+        assert_eq!(protocol.send(addr, "first").unwrap(), "first");
+        assert!(matches!(protocol.send(addr, "stall"), Err(Error::IO(_))));
+        thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), ["first", "stall"]);
+    }
+
+    #[test]
+    fn pooled_protocol_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let protocol = PooledProtocol::default().with_keepalive(Duration::from_secs(10));
+
+        let with_keepalive = protocol.connect(addr, protocol.timeout).unwrap();
+        let without_keepalive = PooledProtocol::default()
+            .connect(addr, protocol.timeout)
+            .unwrap();
+
+        assert!(SockRef::from(&with_keepalive).keepalive().unwrap());
+        assert!(!SockRef::from(&without_keepalive).keepalive().unwrap());
+    }
+
+    fn serve_length_prefix(length: u32) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            if let Ok((mut socket, _)) = listener.accept() {
+                let _ = socket.read(&mut [0_u8; 1024]);
+                let mut prefix = [0_u8; 4];
+                BigEndian::write_u32(&mut prefix, length);
+                let _ = socket.write_all(&prefix);
+                let _ = socket.write_all(&[0_u8; 64]);
+                thread::sleep(Duration::from_millis(200));
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn protocol_send_rejects_long_response() {
+        let addr = serve_length_prefix(u32::MAX);
+
+        match DefaultProtocol::default().send(addr, "{}") {
+            Err(Error::Frame(FrameError::TooLong { length, max })) => {
+                assert_eq!(length, u32::MAX as usize);
+                assert_eq!(max, DEFAULT_MAX_RESPONSE_SIZE);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn protocol_max_response_size() {
+        let addr = serve_length_prefix(1024);
+        let protocol = DefaultProtocol::default().with_max_response_size(512);
+
+        match protocol.send(addr, "{}") {
+            Err(Error::Frame(FrameError::TooLong { length, max })) => {
+                assert_eq!((length, max), (1024, 512));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn protocol_unlimited_response_size() {
+        let addr = serve_length_prefix(u32::MAX);
+        let protocol = DefaultProtocol::default().with_max_response_size(usize::MAX);
+
+        match protocol.send(addr, "{}") {
+            Err(Error::Frame(FrameError::LengthMismatch { expected, actual })) => {
+                assert_eq!((expected, actual), (u32::MAX as usize, 64));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pooled_protocol_max_response_size() {
+        let addr = serve_length_prefix(1024);
+        let protocol = PooledProtocol::default().with_max_response_size(512);
+
+        match protocol.send(addr, "{}") {
+            Err(Error::Frame(FrameError::TooLong { length, max })) => {
+                assert_eq!((length, max), (1024, 512));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn udp_protocol_send() {
+        let protocol = UdpProtocol::default();
+        let msg = "{\"system\":{\"set_relay_state\":{\"state\":1}}}";
+        let resp = "great response";
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buffer = [0_u8; 1024];
+            let (read, from) = socket.recv_from(&mut buffer).unwrap();
+            socket.send_to(&encrypt(resp)[4..], from).unwrap();
+            decrypt(&mut buffer[0..read])
+        });
+
+        let result = protocol.send(addr, msg).unwrap();
+
+        assert_eq!(result, resp.to_string());
+        assert_eq!(server.join().unwrap(), msg);
+    }
+}
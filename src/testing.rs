@@ -0,0 +1,147 @@
+//! Test support for code that drives `TPLink` devices
+//!
+//! Enable the `testing` feature to use this module.
+//!
+//! [`MockProtocol`](struct.MockProtocol.html) is a transport that returns
+//! queued responses and records every command sent, so devices can be
+//! exercised without a network.
+//!
+//! ```
+//! use tplinker::{
+//!     capabilities::Switch,
+//!     devices::{RawDevice, HS100},
+//!     testing::MockProtocol,
+//! };
+//!
+//! let mock = MockProtocol::new();
+//! mock.push_response(r#"{"system":{"set_relay_state":{"err_code":0}}}"#);
+//!
+//! let addr = "192.168.0.99:9999".parse().unwrap();
+//! let device = HS100::from_raw(RawDevice::with_protocol(addr, mock.clone()));
+//! device.switch_on().unwrap();
+//!
+//! assert_eq!(
+//!     mock.sent(),
+//!     vec![r#"{"system":{"set_relay_state":{"state":1}}}"#]
+//! );
+//! ```
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{error::Error, protocol::Protocol};
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: VecDeque<Result<String, Error>>,
+    requests: Vec<(SocketAddr, String)>,
+}
+
+/// A transport returning queued responses and recording the commands sent
+///
+/// Clones share the same queue and record so a clone can be given to a device
+/// and the original kept to make assertions.
+#[derive(Clone, Debug, Default)]
+pub struct MockProtocol {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockProtocol {
+    /// Make a mock with no queued responses
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a JSON response to return from the next command
+    pub fn push_response(&self, response: &str) {
+        self.push_result(Ok(response.to_string()));
+    }
+
+    /// Queue an error to return from the next command
+    pub fn push_error(&self, error: Error) {
+        self.push_result(Err(error));
+    }
+
+    /// Queue an I/O error of the given kind to return from the next command
+    pub fn push_io_error(&self, kind: io::ErrorKind) {
+        self.push_error(Error::IO(io::Error::new(kind, "mock I/O error")));
+    }
+
+    fn push_result(&self, result: Result<String, Error>) {
+        self.state.lock().unwrap().responses.push_back(result);
+    }
+
+    /// Every command sent along with the address it was sent to, in order
+    pub fn requests(&self) -> Vec<(SocketAddr, String)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Every command sent, in order
+    pub fn sent(&self) -> Vec<String> {
+        self.requests().into_iter().map(|(_, msg)| msg).collect()
+    }
+
+    /// Number of queued responses that have not been returned yet
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().responses.len()
+    }
+}
+
+impl Protocol for MockProtocol {
+    fn send(&self, ip: SocketAddr, msg: &str) -> Result<String, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push((ip, msg.to_string()));
+        state
+            .responses
+            .pop_front()
+            .unwrap_or_else(|| Err(Error::from("no mock response queued")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        capabilities::{DeviceActions, Switch},
+        datatypes::tests::HS100_JSON_ON,
+        devices::{RawDevice, HS100},
+    };
+
+    fn device(mock: &MockProtocol) -> HS100<MockProtocol> {
+        HS100::from_raw(RawDevice::with_protocol(
+            "127.0.0.1:9999".parse().unwrap(),
+            mock.clone(),
+        ))
+    }
+
+    #[test]
+    fn returns_queued_responses_in_order() {
+        let mock = MockProtocol::new();
+        mock.push_response(HS100_JSON_ON);
+        mock.push_io_error(io::ErrorKind::TimedOut);
+        let device = device(&mock);
+
+        assert!(device.is_on().unwrap());
+        assert!(matches!(device.sysinfo(), Err(Error::IO(_))));
+        assert!(matches!(device.sysinfo(), Err(Error::Other(_))));
+        assert_eq!(mock.remaining(), 0);
+    }
+
+    #[test]
+    fn records_commands() {
+        let mock = MockProtocol::new();
+        mock.push_response(HS100_JSON_ON);
+        device(&mock).sysinfo().unwrap();
+
+        assert_eq!(
+            mock.requests(),
+            vec![(
+                "127.0.0.1:9999".parse().unwrap(),
+                String::from(r#"{"system":{"get_sysinfo":null}}"#)
+            )]
+        );
+    }
+}